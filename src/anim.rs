@@ -0,0 +1,287 @@
+use nalgebra::base::*;
+use std::path::Path;
+use std::ops::RangeInclusive;
+
+use crate::renderer::*;
+use crate::output::Options;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Interp {
+    Linear,
+    Bezier,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Target {
+    CamPos,
+    Rot,
+    Focus,
+    Aperture,
+    SkyLight,
+    ObjPos(usize),
+    ObjRot(usize),
+    ObjScale(usize),
+    ObjColor(usize),
+    ObjEmit(usize),
+    ObjShininess(usize),
+    ObjRough(usize),
+}
+
+impl std::fmt::Display for Target {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            Target::CamPos => write!(f, "cam_pos"),
+            Target::Rot => write!(f, "rot"),
+            Target::Focus => write!(f, "focus"),
+            Target::Aperture => write!(f, "aperture"),
+            Target::SkyLight => write!(f, "sky_light"),
+            Target::ObjPos(i) => write!(f, "obj {i} pos"),
+            Target::ObjRot(i) => write!(f, "obj {i} rot"),
+            Target::ObjScale(i) => write!(f, "obj {i} scale"),
+            Target::ObjColor(i) => write!(f, "obj {i} color"),
+            Target::ObjEmit(i) => write!(f, "obj {i} emit"),
+            Target::ObjShininess(i) => write!(f, "obj {i} shininess"),
+            Target::ObjRough(i) => write!(f, "obj {i} rough"),
+        }
+    }
+}
+
+impl Target {
+    fn width(&self) -> usize {
+        match self {
+            Target::Rot => 2,
+            Target::Focus | Target::Aperture | Target::ObjShininess(_) | Target::ObjRough(_) => 1,
+            _ => 3,
+        }
+    }
+
+    fn object(&self) -> Option<usize> {
+        match *self {
+            Target::ObjPos(i) | Target::ObjRot(i) | Target::ObjScale(i)
+                | Target::ObjColor(i) | Target::ObjEmit(i)
+                | Target::ObjShininess(i) | Target::ObjRough(i) => Some(i),
+            _ => None,
+        }
+    }
+}
+
+pub struct Track {
+    pub target: Target,
+    pub interp: Interp,
+    pub keys: Vec<(f64, Vec<f64>)>,
+}
+
+impl Track {
+    pub fn sample(&self, t: f64) -> Vec<f64> {
+        let k = &self.keys;
+        let last = &k[k.len() - 1];
+        if t <= k[0].0 { return k[0].1.clone() }
+        if t >= last.0 { return last.1.clone() }
+
+        let i = k.partition_point(|(kt, _)| *kt <= t) - 1;
+        let (t0, v0) = &k[i];
+        let (t1, v1) = &k[i + 1];
+        let f = (t - t0) / (t1 - t0);
+
+        match self.interp {
+            Interp::Linear => v0.iter().zip(v1).map(|(a, b)| a + (b - a) * f).collect(),
+            Interp::Bezier => {
+                // control points are picked from the neighbouring keys (Catmull-Rom with the tangents over time),
+                // so the curve passes through every key with a continuous tangent however far apart the keys are.
+                // the first and last key get a neighbour with the same value as far away as the one they have
+                let h = t1 - t0;
+                let (tp, vp) = if i > 0 { (k[i - 1].0, &k[i - 1].1) } else { (t0 - h, v0) };
+                let (tn, vn) = k.get(i + 2).map_or((t1 + h, v1), |k| (k.0, &k.1));
+
+                (0..v0.len()).map(|j| {
+                    let p1 = v0[j] + (v1[j] - vp[j]) * h / (3.0 * (t1 - tp));
+                    let p2 = v1[j] - (vn[j] - v0[j]) * h / (3.0 * (tn - t0));
+                    let g = 1.0 - f;
+                    g * g * g * v0[j] + 3.0 * g * g * f * p1 + 3.0 * g * f * f * p2 + f * f * f * v1[j]
+                }).collect()
+            },
+        }
+    }
+}
+
+pub struct Animation {
+    pub fps: f64,
+    pub tracks: Vec<Track>,
+}
+
+impl Animation {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let mut s = format!("fps {}\n", self.fps);
+        for tr in self.tracks.iter() {
+            let interp = match tr.interp { Interp::Linear => "linear", Interp::Bezier => "bezier" };
+            s += &format!("track {} {interp}\n", tr.target);
+            for (t, v) in tr.keys.iter() {
+                s += &format!("{t}");
+                for v in v.iter() {
+                    s += &format!(" {v}");
+                }
+                s += "\n";
+            }
+        }
+        std::fs::write(path, s)?;
+        Ok(())
+    }
+
+    pub fn from_poses(poses: &[(f64, Pose)], fps: f64) -> Self {
+        let track = |target, f: &dyn Fn(&Pose) -> Vec<f64>| Track {
+            target,
+            interp: Interp::Linear,
+            keys: poses.iter().map(|(t, p)| (*t, f(p))).collect(),
+        };
+
+        Animation { fps, tracks: vec![
+            track(Target::CamPos, &|p| p.cam_pos.iter().copied().collect()),
+            track(Target::Rot, &|p| p.rot.iter().copied().collect()),
+            track(Target::Focus, &|p| vec![p.focus]),
+            track(Target::Aperture, &|p| vec![p.aperture]),
+        ]}
+    }
+
+    pub fn duration(&self) -> f64 {
+        self.tracks.iter().filter_map(|t| t.keys.last()).map(|k| k.0).fold(0.0, f64::max)
+    }
+
+    pub fn parse(s: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut anim = Animation { fps: 24.0, tracks: Vec::new() };
+
+        for (ln, l) in s.lines().enumerate() {
+            let l = l.split('#').next().unwrap().trim();
+            let w: Vec<&str> = l.split_whitespace().collect();
+            let err = |m: &str| format!("line {}: {m}", ln + 1);
+
+            match w.first() {
+                None => (),
+                Some(&"fps") => {
+                    anim.fps = w.get(1).ok_or_else(|| err("missing fps"))?.parse().map_err(|e| err(&format!("fps: {e}")))?;
+                    // frame times are divided by it
+                    if !(anim.fps.is_finite() && anim.fps > 0.0) { return Err(err("fps has to be above 0").into()) }
+                },
+                Some(&"track") => {
+                    let (target, rest) = match &w[1..] {
+                        ["cam_pos", r @ ..] => (Target::CamPos, r),
+                        ["rot", r @ ..] => (Target::Rot, r),
+                        ["focus", r @ ..] => (Target::Focus, r),
+                        ["aperture", r @ ..] => (Target::Aperture, r),
+                        ["sky_light", r @ ..] => (Target::SkyLight, r),
+                        ["obj", i, p, r @ ..] => {
+                            let i = i.parse().map_err(|e| err(&format!("object index: {e}")))?;
+                            (match *p {
+                                "pos" => Target::ObjPos(i),
+                                "rot" => Target::ObjRot(i),
+                                "scale" => Target::ObjScale(i),
+                                "color" => Target::ObjColor(i),
+                                "emit" => Target::ObjEmit(i),
+                                "shininess" => Target::ObjShininess(i),
+                                "rough" => Target::ObjRough(i),
+                                _ => return Err(err(&format!("unknown object property `{p}`")).into()),
+                            }, r)
+                        },
+                        _ => return Err(err("unknown track target").into()),
+                    };
+
+                    let interp = match rest {
+                        [] | ["linear"] => Interp::Linear,
+                        ["bezier"] => Interp::Bezier,
+                        _ => return Err(err("unknown interpolation").into()),
+                    };
+
+                    anim.tracks.push(Track { target, interp, keys: Vec::new() });
+                },
+                Some(_) => {
+                    let tr = anim.tracks.last_mut().ok_or_else(|| err("key outside of a track"))?;
+                    let n = w.iter().map(|v| v.parse()).collect::<Result<Vec<f64>, _>>().map_err(|e| err(&e.to_string()))?;
+                    if n.len() != tr.target.width() + 1 {
+                        return Err(err(&format!("expected time and {} values", tr.target.width())).into());
+                    }
+                    if tr.keys.last().is_some_and(|k| k.0 >= n[0]) {
+                        return Err(err("key times must be increasing").into());
+                    }
+                    tr.keys.push((n[0], n[1..].to_vec()));
+                },
+            }
+        }
+
+        if let Some(tr) = anim.tracks.iter().find(|t| t.keys.is_empty()) {
+            return Err(format!("track `{}` has no keys", tr.target).into());
+        }
+
+        Ok(anim)
+    }
+
+    pub fn check(&self, rs: &RendererState) -> Result<(), Box<dyn std::error::Error>> {
+        for t in self.tracks.iter() {
+            if t.target.object().is_some_and(|i| i >= rs.scene.len()) {
+                return Err(format!("track `{}` refers to a missing object, scene has {}", t.target, rs.scene.len()).into());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn apply(&self, rs: &mut RendererState, t: f64) {
+        for tr in self.tracks.iter() {
+            let v = tr.sample(t);
+            let v3 = || Vector3::new(v[0], v[1], v[2]);
+
+            match tr.target {
+                Target::CamPos => rs.cam_pos = v3(),
+                Target::Rot => rs.rot = Vector2::new(v[0], v[1]),
+                Target::Focus => rs.focus = v[0],
+                Target::Aperture => rs.aperture = v[0],
+                Target::SkyLight => rs.sky_light = v3(),
+                Target::ObjPos(i) | Target::ObjRot(i) | Target::ObjScale(i) => {
+                    let o = &mut rs.scene[i];
+                    let mut x = *o.transform();
+                    match tr.target {
+                        Target::ObjPos(_) => x.pos = v3(),
+                        Target::ObjRot(_) => x.rot = v3(),
+                        _ => x.scale = v3(),
+                    }
+                    o.set_transform(x);
+                },
+                Target::ObjColor(i) => rs.scene[i].material.color = v3(),
+                Target::ObjEmit(i) => rs.scene[i].material.emit_color = v3(),
+                Target::ObjShininess(i) => rs.scene[i].material.shininess = v[0],
+                Target::ObjRough(i) => rs.scene[i].material.rough = v[0],
+            }
+        }
+    }
+}
+
+// frames are named after `template` with the frame number added, `frames/frame.exr` gives `frames/frame_00001.exr`
+pub fn render_frames(rs: &mut RendererState, anim: &Animation, frames: RangeInclusive<usize>, template: &Path, size: usize, passes: usize, opts: Options) -> Result<(), Box<dyn std::error::Error>> {
+    anim.check(rs)?;
+    crate::output::format(template)?;
+    let dir = template.parent().unwrap_or(Path::new("."));
+    std::fs::create_dir_all(dir)?;
+
+    let stem = template.file_stem().and_then(|s| s.to_str()).unwrap_or("frame");
+    let ext = template.extension().and_then(|e| e.to_str()).unwrap_or("png");
+    for f in frames {
+        let path = dir.join(format!("{stem}_{f:05}.{ext}"));
+        if path.exists() {
+            println!("frame {f} already on disk, skipping");
+            continue
+        }
+
+        anim.apply(rs, f as f64 / anim.fps);
+        let img = render_passes(rs, size, passes);
+
+        // written under another name first so a killed render never leaves a frame that looks finished
+        let tmp = path.with_extension(format!("{ext}.part"));
+        crate::output::save(&tmp, &crate::output::layers(&img, opts), opts)?;
+        std::fs::rename(tmp, path)?;
+
+        println!("frame {f} done");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,252 @@
+use nalgebra::base::*;
+use std::f64::consts::TAU;
+use std::path::Path;
+use std::time::Instant;
+
+use crate::renderer::{Material, Object, Pose, RendererState};
+use crate::stats::Snapshot;
+
+// the same for every scene and every run, so the numbers only change when the renderer does
+pub const SIZE: usize = 64;
+pub const PASSES: usize = 2;
+pub const SEED: u64 = 1;
+// timed renders of every scene after an untimed one, the fastest counts
+pub const REPEATS: usize = 3;
+// slower than the baseline by more than this counts as a regression
+pub const TOLERANCE: f64 = 0.15;
+
+type Scene = Result<(Vec<Object>, Pose), Box<dyn std::error::Error>>;
+type Build = fn() -> Scene;
+
+pub const SCENES: &[(&str, Build)] = &[
+    ("balls",   balls),
+    ("mesh",    mesh),
+    ("cornell", cornell),
+];
+
+// the generated scene without the obj, which might not be there
+fn balls() -> Scene {
+    let scene = crate::scene::build(&crate::balls_and_floor(SEED))?;
+    Ok((scene, Pose { cam_pos: Vector3::new(0.0, 2.0, -7.0), rot: Vector2::new(0.2, 0.0), ..Default::default() }))
+}
+
+// a smooth torus of about a thousand triangles, which every ray has to test one by one
+fn mesh() -> Scene {
+    let mut scene = crate::scene::build(&crate::scene::parse(MESH_SCENE)?)?;
+    for (m, _) in crate::scene::parse_obj(&torus(32, 16, 1.0, 0.4), 1.0, Vector3::new(0.0, 0.8, 0.0))? {
+        scene.push(Object::new(Box::new(m), Material {
+            color: Vector3::new(0.9, 0.6, 0.2),
+            emit_color: Vector3::default(),
+            shininess: 0.5,
+            rough: 0.3,
+        }));
+    }
+    Ok((scene, Pose { cam_pos: Vector3::new(0.0, 2.0, -3.0), rot: Vector2::new(0.4, 0.0), ..Default::default() }))
+}
+
+const MESH_SCENE: &str = "\
+floor 0 100 color 0.8 0.8 0.8 shininess 0.1 rough 0.9
+sphere -2 3 1 0.4 color 1 1 1 emit 10 9 7
+";
+
+fn cornell() -> Scene {
+    let scene = crate::scene::build(&crate::scene::parse(CORNELL_BOX)?)?;
+    Ok((scene, Pose { cam_pos: Vector3::new(0.0, 1.0, -2.1), ..Default::default() }))
+}
+
+// open towards -z, the red wall shows up on the left
+const CORNELL_BOX: &str = "\
+triangle -1 0 -1 1 0 1 1 0 -1 color 0.73 0.73 0.73 shininess 0 rough 1
+triangle -1 0 -1 -1 0 1 1 0 1 color 0.73 0.73 0.73 shininess 0 rough 1
+triangle -1 2 -1 1 2 -1 1 2 1 color 0.73 0.73 0.73 shininess 0 rough 1
+triangle -1 2 -1 1 2 1 -1 2 1 color 0.73 0.73 0.73 shininess 0 rough 1
+triangle -1 0 1 1 2 1 1 0 1 color 0.73 0.73 0.73 shininess 0 rough 1
+triangle -1 0 1 -1 2 1 1 2 1 color 0.73 0.73 0.73 shininess 0 rough 1
+triangle -1 0 -1 -1 2 1 -1 0 1 color 0.12 0.45 0.15 shininess 0 rough 1
+triangle -1 0 -1 -1 2 -1 -1 2 1 color 0.12 0.45 0.15 shininess 0 rough 1
+triangle 1 0 -1 1 0 1 1 2 1 color 0.65 0.05 0.05 shininess 0 rough 1
+triangle 1 0 -1 1 2 1 1 2 -1 color 0.65 0.05 0.05 shininess 0 rough 1
+triangle -0.3 1.99 -0.3 0.3 1.99 -0.3 0.3 1.99 0.3 color 1 1 1 emit 15 13 10 shininess 0 rough 1
+triangle -0.3 1.99 -0.3 0.3 1.99 0.3 -0.3 1.99 0.3 color 1 1 1 emit 15 13 10 shininess 0 rough 1
+sphere -0.4 0.4 0.3 0.4 color 0.9 0.9 0.9 shininess 0.9 rough 0.05
+sphere 0.45 0.35 -0.3 0.35 color 0.73 0.73 0.73 shininess 0 rough 1
+";
+
+// lying flat around the y axis, wound so the triangles face outwards
+fn torus(rings: usize, sides: usize, r0: f64, r1: f64) -> String {
+    let mut s = String::new();
+    for i in 0..rings {
+        for j in 0..sides {
+            let (u, v) = (i as f64 / rings as f64 * TAU, j as f64 / sides as f64 * TAU);
+            let n = Vector3::new(u.cos() * v.cos(), v.sin(), u.sin() * v.cos());
+            let p = Vector3::new(u.cos(), 0.0, u.sin()) * r0 + n * r1;
+            s += &format!("v {} {} {}\nvn {} {} {}\n", p[0], p[1], p[2], n[0], n[1], n[2]);
+        }
+    }
+    let k = |i: usize, j: usize| (i % rings) * sides + j % sides + 1;
+    for i in 0..rings {
+        for j in 0..sides {
+            let (a, b, c, d) = (k(i, j), k(i + 1, j), k(i + 1, j + 1), k(i, j + 1));
+            s += &format!("f {a}//{a} {c}//{c} {b}//{b}\nf {a}//{a} {d}//{d} {c}//{c}\n");
+        }
+    }
+    s
+}
+
+pub struct Run {
+    pub name: &'static str,
+    pub stats: Snapshot,
+    pub seconds: f64,
+    // resident while the scene and the image are still there
+    pub memory: Option<usize>,
+}
+
+impl Run {
+    fn json(&self) -> String {
+        let mut extra = vec![("size", SIZE as f64), ("passes", PASSES as f64), ("seed", SEED as f64), ("repeats", REPEATS as f64)];
+        if let Some(m) = self.memory {
+            extra.push(("memory_mib", m as f64 / (1024.0 * 1024.0)));
+        }
+        self.stats.json(&extra, std::time::Duration::from_secs_f64(self.seconds))
+    }
+}
+
+// renders every scene, writes the results to `out` and compares them with `baseline`, returns how many got slower
+pub fn run(out: Option<&Path>, baseline: Option<&Path>, tolerance: f64) -> Result<usize, Box<dyn std::error::Error>> {
+    let baseline = match baseline {
+        Some(p) => Some(parse(&std::fs::read_to_string(p)?).map_err(|e| format!("{}: {e}", p.display()))?),
+        None => None,
+    };
+
+    println!("{SIZE}x{SIZE}, {} samples per pixel, seed {SEED}, best of {REPEATS}", PASSES * crate::renderer::SAMPLES_LVL);
+    let mut runs = Vec::new();
+    let mut regressions = 0;
+    for (name, build) in SCENES {
+        let (scene, pose) = build()?;
+        let mut rs = RendererState { scene, seed: SEED, ..Default::default() };
+        rs.set_pose(pose);
+
+        // the first one warms up the caches and the thread pool
+        let mut repeats = Vec::new();
+        for _ in 0..=REPEATS {
+            rs.stats.reset();
+            let start = Instant::now();
+            let img = crate::renderer::render_passes(&mut rs, SIZE, PASSES);
+            let seconds = start.elapsed().as_secs_f64();
+            repeats.push(Run { name, stats: rs.stats.snapshot(), seconds, memory: crate::stats::memory_use() });
+            drop(img);
+        }
+        let run = repeats.into_iter().skip(1).min_by(|a, b| a.seconds.total_cmp(&b.seconds)).unwrap();
+        let seconds = run.seconds;
+
+        let mib = run.memory.map_or("n/a".to_string(), |m| format!("{:.1} MiB", m as f64 / (1024.0 * 1024.0)));
+        println!("{name:<8} {seconds:>7.2}s {:>7.2} Mray/s {mib:>10}", run.stats.mrays_per_sec());
+
+        if let Some(b) = baseline.as_ref().and_then(|b| b.get(name)) {
+            let (Some(mrays), Some(rays)) = (b.get("mrays_per_sec").and_then(Json::num), b.get("rays").and_then(Json::num)) else {
+                return Err(format!("the baseline for {name} has no mrays_per_sec or rays").into());
+            };
+            let change = run.stats.mrays_per_sec() / mrays - 1.0;
+            let slower = change < -tolerance;
+            regressions += slower as usize;
+            println!("         {:+.1}% against {mrays:.2} Mray/s{}", change * 100.0, if slower { ", REGRESSION" } else { "" });
+            if rays as u64 != run.stats.rays() {
+                println!("         traced {} rays instead of {rays}, so the scene or the renderer changed", run.stats.rays());
+            }
+        }
+        runs.push(run);
+    }
+
+    if let Some(out) = out {
+        let scenes: Vec<String> = runs.iter().map(|r| format!("  \"{}\": {}", r.name, r.json().trim_end().replace('\n', "\n  "))).collect();
+        std::fs::write(out, format!("{{\n{}\n}}\n", scenes.join(",\n")))?;
+    }
+    Ok(regressions)
+}
+
+// just enough JSON for what `run` writes: objects of numbers, strings and more objects
+enum Json {
+    Num(f64),
+    Str,
+    Obj(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn get(&self, k: &str) -> Option<&Json> {
+        match self {
+            Json::Obj(f) => f.iter().find(|f| f.0 == k).map(|f| &f.1),
+            _ => None,
+        }
+    }
+
+    fn num(&self) -> Option<f64> {
+        match self {
+            Json::Num(n) => Some(*n),
+            _ => None,
+        }
+    }
+}
+
+fn parse(s: &str) -> Result<Json, String> {
+    let mut c = s.char_indices().peekable();
+    let v = value(s, &mut c)?;
+    skip_space(&mut c);
+    match c.next() {
+        None => Ok(v),
+        Some((i, _)) => Err(format!("unexpected `{}` after the end", &s[i..].chars().next().unwrap())),
+    }
+}
+
+type Chars<'a> = std::iter::Peekable<std::str::CharIndices<'a>>;
+
+fn skip_space(c: &mut Chars) {
+    while c.next_if(|(_, ch)| ch.is_whitespace()).is_some() {}
+}
+
+fn value(s: &str, c: &mut Chars) -> Result<Json, String> {
+    skip_space(c);
+    match c.peek().copied() {
+        Some((_, '{')) => {
+            c.next();
+            let mut fields = Vec::new();
+            loop {
+                skip_space(c);
+                if c.next_if(|(_, ch)| *ch == '}').is_some() && fields.is_empty() { break }
+                let k = string(c)?;
+                skip_space(c);
+                if c.next().map(|(_, ch)| ch) != Some(':') { return Err(format!("expected `:` after \"{k}\"")) }
+                fields.push((k, value(s, c)?));
+                skip_space(c);
+                match c.next() {
+                    Some((_, ',')) => (),
+                    Some((_, '}')) => break,
+                    _ => return Err("expected `,` or `}`".to_string()),
+                }
+            }
+            Ok(Json::Obj(fields))
+        },
+        Some((_, '"')) => string(c).map(|_| Json::Str),
+        Some((i, ch)) => {
+            let mut end = i;
+            while let Some((j, _)) = c.next_if(|(_, ch)| ch.is_ascii_digit() || "+-.eE".contains(*ch)) {
+                end = j + 1;
+            }
+            if end == i { return Err(format!("unexpected `{ch}`")) }
+            s[i..end].parse().map(Json::Num).map_err(|_| format!("`{}` is not a number", &s[i..end]))
+        },
+        None => Err("unexpected end".to_string()),
+    }
+}
+
+fn string(c: &mut Chars) -> Result<String, String> {
+    if c.next().map(|(_, ch)| ch) != Some('"') { return Err("expected a string".to_string()) }
+    let mut r = String::new();
+    loop {
+        match c.next() {
+            Some((_, '"')) => return Ok(r),
+            Some((_, '\\')) => r.extend(c.next().map(|(_, ch)| ch)),
+            Some((_, ch)) => r.push(ch),
+            None => return Err("unterminated string".to_string()),
+        }
+    }
+}
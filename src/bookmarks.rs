@@ -0,0 +1,57 @@
+use nalgebra::base::*;
+use std::path::{Path, PathBuf};
+
+use crate::renderer::Pose;
+
+#[derive(Default)]
+pub struct Bookmarks {
+    pub path: PathBuf,
+    pub poses: [Option<Pose>; 10],
+}
+
+impl Bookmarks {
+    // bookmarks live next to the scene they belong to, e.g. `scene.txt.bookmarks`
+    pub fn for_scene(scene: &Path) -> Self {
+        let mut p = scene.as_os_str().to_owned();
+        p.push(".bookmarks");
+        Self { path: p.into(), poses: [None; 10] }
+    }
+
+    pub fn load(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.path.exists() { return Ok(()) }
+
+        for (ln, l) in std::fs::read_to_string(&self.path)?.lines().enumerate() {
+            if l.trim().is_empty() { continue }
+
+            let err = || format!("{}:{}: expected `<slot> <x> <y> <z> <rot x> <rot y> <focus> <aperture>`", self.path.display(), ln + 1);
+            let n = l.split_whitespace().map(|v| v.parse()).collect::<Result<Vec<f64>, _>>().map_err(|_| err())?;
+            if n.len() != 8 || n[0] < 0.0 || n[0] >= 10.0 {
+                return Err(err().into());
+            }
+
+            self.poses[n[0] as usize] = Some(Pose {
+                cam_pos: Vector3::new(n[1], n[2], n[3]),
+                rot: Vector2::new(n[4], n[5]),
+                focus: n[6],
+                aperture: n[7],
+            });
+        }
+
+        Ok(())
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let mut s = String::new();
+        for (i, p) in self.poses.iter().enumerate() {
+            if let Some(p) = p {
+                s += &format!("{i} {} {} {} {} {} {} {}\n",
+                    p.cam_pos[0], p.cam_pos[1], p.cam_pos[2],
+                    p.rot[0], p.rot[1],
+                    p.focus, p.aperture
+                );
+            }
+        }
+        std::fs::write(&self.path, s)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+use nalgebra::base::*;
+use std::io::{Read, Write, BufReader, BufWriter};
+use std::path::Path;
+
+use crate::renderer::{Pixel, Pose};
+
+const MAGIC: &[u8; 4] = b"TRCK";
+const VERSION: u32 = 4;
+// magic, version, scene hash, size, passes and the 7 numbers of the pose
+const HEADER_BYTES: u64 = 4 + 4 + 8 + 8 + 8 + 7 * 8;
+// 6 vectors and 7 numbers
+const PIXEL_BYTES: u64 = (6 * 3 + 7) * 8;
+
+// the summed (not yet averaged) buffers of every pass, so rendering can pick up where it stopped
+pub struct Checkpoint {
+    pub scene_hash: u64,
+    pub passes: usize,
+    pub pose: Pose,
+    pub img: Vec<Vec<Pixel>>,
+}
+
+impl Checkpoint {
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        // written next to the old one first so a crash mid-write doesn't lose both
+        let tmp = path.with_extension("part");
+        let mut w = BufWriter::new(std::fs::File::create(&tmp)?);
+
+        w.write_all(MAGIC)?;
+        w.write_all(&VERSION.to_le_bytes())?;
+        w.write_all(&self.scene_hash.to_le_bytes())?;
+        w.write_all(&(self.img.len() as u64).to_le_bytes())?;
+        w.write_all(&(self.passes as u64).to_le_bytes())?;
+
+        let p = &self.pose;
+        for v in [p.cam_pos[0], p.cam_pos[1], p.cam_pos[2], p.rot[0], p.rot[1], p.focus, p.aperture] {
+            w.write_all(&v.to_le_bytes())?;
+        }
+
+        for px in self.img.iter().flatten() {
+            let vs = [px.color, px.normal, px.albedo, px.direct, px.indirect, px.emission];
+            for v in vs.iter().flat_map(|v| v.iter()).chain(&[px.depth, px.object, px.material, px.weight, px.samples, px.lum, px.lum2]) {
+                w.write_all(&v.to_le_bytes())?;
+            }
+        }
+
+        w.into_inner()?.sync_all()?;
+        std::fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let file = std::fs::File::open(path)?;
+        let len = file.metadata()?.len();
+        let mut r = BufReader::new(file);
+
+        let mut magic = [0; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(format!("{} is not a termray checkpoint", path.display()).into());
+        }
+
+        let version = read_u64(&mut r, 4)?;
+        if version != VERSION as u64 {
+            return Err(format!("{} is a version {version} checkpoint, only version {VERSION} is supported", path.display()).into());
+        }
+
+        let scene_hash = read_u64(&mut r, 8)?;
+        let size = read_u64(&mut r, 8)?;
+        let passes = read_u64(&mut r, 8)? as usize;
+        // checked before the image is allocated, so a damaged size can't ask for more memory than there is
+        if size.checked_mul(size).and_then(|n| n.checked_mul(PIXEL_BYTES)).and_then(|n| n.checked_add(HEADER_BYTES)) != Some(len) {
+            return Err(format!("{} is damaged, it's {len} bytes long but says it holds a {size}x{size} image", path.display()).into());
+        }
+        let size = size as usize;
+
+        let mut f = || -> Result<f64, std::io::Error> { Ok(f64::from_bits(read_u64(&mut r, 8)?)) };
+        let pose = Pose {
+            cam_pos: Vector3::new(f()?, f()?, f()?),
+            rot: Vector2::new(f()?, f()?),
+            focus: f()?,
+            aperture: f()?,
+        };
+
+        let mut img = vec![vec![Pixel::default(); size]; size];
+        for px in img.iter_mut().flatten() {
+            let mut v3 = || -> Result<Vector3<f64>, std::io::Error> { Ok(Vector3::new(f()?, f()?, f()?)) };
+            px.color = v3()?;
+            px.normal = v3()?;
+            px.albedo = v3()?;
+            px.direct = v3()?;
+            px.indirect = v3()?;
+            px.emission = v3()?;
+            px.depth = f()?;
+            px.object = f()?;
+            px.material = f()?;
+            px.weight = f()?;
+            px.samples = f()?;
+            px.lum = f()?;
+            px.lum2 = f()?;
+        }
+
+        Ok(Self { scene_hash, passes, pose, img })
+    }
+
+    pub fn check(&self, path: &Path, scene_hash: u64, size: usize) -> Result<(), Box<dyn std::error::Error>> {
+        if self.scene_hash != scene_hash {
+            return Err(format!(
+                "{} was rendered from a different scene (hash {:016x}, current scene is {scene_hash:016x}), \
+                 save the scene it was made from and load it with --scene to resume",
+                path.display(), self.scene_hash
+            ).into());
+        }
+        if self.img.len() != size {
+            return Err(format!("{} is {}x{} but this render is {size}x{size}", path.display(), self.img.len(), self.img.len()).into());
+        }
+        Ok(())
+    }
+}
+
+fn read_u64(r: &mut impl Read, n: usize) -> Result<u64, std::io::Error> {
+    let mut b = [0; 8];
+    r.read_exact(&mut b[..n])?;
+    Ok(u64::from_le_bytes(b))
+}
@@ -0,0 +1,307 @@
+use nalgebra::base::*;
+use std::f64::consts::PI;
+use std::path::Path;
+
+use crate::renderer::{Pixel, RendererState};
+use crate::tonemap::{luminance, Tonemap};
+
+type Image = Vec<Vec<Vector3<f64>>>;
+type Plane = Vec<Vec<f64>>;
+
+// how far off an image is from a reference. the first four are on linear radiance (PSNR against a peak of 1),
+// SSIM and FLIP on what a screen would show
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Metrics {
+    pub mse: f64,
+    pub rmse: f64,
+    pub psnr: f64,
+    // every difference relative to the reference's value there, so dark parts count as much as bright ones
+    pub relmse: f64,
+    // 1 for the same image
+    pub ssim: f64,
+    // 0 for the same image
+    pub flip: f64,
+}
+
+pub const METRICS: &[&str] = &["mse", "rmse", "psnr", "relmse", "ssim", "flip"];
+
+impl Metrics {
+    pub fn values(&self) -> [f64; 6] {
+        [self.mse, self.rmse, self.psnr, self.relmse, self.ssim, self.flip]
+    }
+}
+
+// returns the FLIP error of every pixel as well
+pub fn compare(img: &[Vec<Vector3<f64>>], reference: &[Vec<Vector3<f64>>]) -> Result<(Metrics, Plane), String> {
+    let size = |i: &[Vec<Vector3<f64>>]| (i.first().map_or(0, |r| r.len()), i.len());
+    let ((w, h), (rw, rh)) = (size(img), size(reference));
+    if (w, h) != (rw, rh) { return Err(format!("the images are {w}x{h} and {rw}x{rh}, they have to be the same size")) }
+    if w == 0 || h == 0 { return Err("the images are empty".to_string()) }
+
+    let n = (w * h * 3) as f64;
+    let pairs = || img.iter().flatten().zip(reference.iter().flatten());
+    let mse = pairs().map(|(a, b)| (a - b).norm_squared()).sum::<f64>() / n;
+    let relmse = pairs().map(|(a, b)| (a - b).component_div(&b.map(|c| c * c + RELMSE_EPSILON)).dot(&(a - b))).sum::<f64>() / n;
+
+    let tm = Tonemap::default();
+    let display = |i: &[Vec<Vector3<f64>>]| -> Image { i.iter().map(|r| r.iter().map(|v| tm.linear(*v)).collect()).collect() };
+    let (a, b) = (display(img), display(reference));
+
+    let flip = flip(&a, &b);
+    let mean = |p: &Plane| p.iter().flatten().sum::<f64>() / (w * h) as f64;
+
+    Ok((Metrics {
+        mse,
+        rmse: mse.sqrt(),
+        psnr: -10.0 * mse.max(1e-20).log10(),
+        relmse,
+        ssim: mean(&ssim(&a, &b)),
+        flip: mean(&flip),
+    }, flip))
+}
+
+// for the plot, when the command line doesn't say
+pub const PASSES: usize = 16;
+
+const RELMSE_EPSILON: f64 = 0.01;
+
+// what a 1D kernel does along x and another along y, with the edges repeated outwards
+fn convolve(img: &Plane, kx: &[f64], ky: &[f64]) -> Plane {
+    let (w, h) = (img[0].len() as isize, img.len() as isize);
+    let (rx, ry) = ((kx.len() / 2) as isize, (ky.len() / 2) as isize);
+    let at = |v: isize, n: isize| v.clamp(0, n - 1) as usize;
+
+    let tmp: Plane = img.iter().map(|row| (0..w).map(|x| {
+        kx.iter().enumerate().map(|(i, k)| k * row[at(x + i as isize - rx, w)]).sum()
+    }).collect()).collect();
+    (0..h).map(|y| (0..w as usize).map(|x| {
+        ky.iter().enumerate().map(|(i, k)| k * tmp[at(y + i as isize - ry, h)][x]).sum()
+    }).collect()).collect()
+}
+
+fn gaussian(sigma: f64, r: usize) -> Vec<f64> {
+    let k: Vec<f64> = (-(r as isize)..=r as isize).map(|x| (-(x * x) as f64 / (2.0 * sigma * sigma)).exp()).collect();
+    let s: f64 = k.iter().sum();
+    k.iter().map(|v| v / s).collect()
+}
+
+fn zip(a: &Plane, b: &Plane, f: impl Fn(f64, f64) -> f64) -> Plane {
+    a.iter().zip(b).map(|(a, b)| a.iter().zip(b).map(|(a, b)| f(*a, *b)).collect()).collect()
+}
+
+fn plane(img: &Image, f: impl Fn(&Vector3<f64>) -> f64) -> Plane {
+    img.iter().map(|r| r.iter().map(&f).collect()).collect()
+}
+
+// Wang et al. on the luminance, with their 11x11 gaussian window
+fn ssim(a: &Image, b: &Image) -> Plane {
+    const C1: f64 = 0.01 * 0.01;
+    const C2: f64 = 0.03 * 0.03;
+
+    let k = gaussian(1.5, 5);
+    let blur = |p: &Plane| convolve(p, &k, &k);
+    let (x, y) = (plane(a, luminance), plane(b, luminance));
+    let (mx, my) = (blur(&x), blur(&y));
+    let (xx, yy, xy) = (blur(&zip(&x, &x, |a, b| a * b)), blur(&zip(&y, &y, |a, b| a * b)), blur(&zip(&x, &y, |a, b| a * b)));
+
+    (0..x.len()).map(|i| (0..x[0].len()).map(|j| {
+        let (mx, my) = (mx[i][j], my[i][j]);
+        let (vx, vy, cov) = (xx[i][j] - mx * mx, yy[i][j] - my * my, xy[i][j] - mx * my);
+        (2.0 * mx * my + C1) * (2.0 * cov + C2) / ((mx * mx + my * my + C1) * (vx + vy + C2))
+    }).collect()).collect()
+}
+
+// LDR-FLIP (Andersson et al., FLIP: A Difference Evaluator for Alternating Images), for linear display colors in 0..1,
+// seen from 0.7 m away on a 0.7 m wide 4K screen
+const PIXELS_PER_DEGREE: f64 = 67.0;
+
+const XYZ_FROM_RGB: Matrix3<f64> = Matrix3::new(
+    0.4124564, 0.3575761, 0.1804375,
+    0.2126729, 0.7151522, 0.0721750,
+    0.0193339, 0.1191920, 0.9503041,
+);
+const RGB_FROM_XYZ: Matrix3<f64> = Matrix3::new(
+     3.2404542, -1.5371385, -0.4985314,
+    -0.9692660,  1.8760108,  0.0415560,
+     0.0556434, -0.2040259,  1.0572252,
+);
+
+fn white() -> Vector3<f64> {
+    XYZ_FROM_RGB * Vector3::repeat(1.0)
+}
+
+fn ycxcz(rgb: &Vector3<f64>) -> Vector3<f64> {
+    let v = (XYZ_FROM_RGB * rgb).component_div(&white());
+    Vector3::new(116.0 * v[1] - 16.0, 500.0 * (v[0] - v[1]), 200.0 * (v[1] - v[2]))
+}
+
+fn rgb_from_ycxcz(c: &Vector3<f64>) -> Vector3<f64> {
+    let y = (c[0] + 16.0) / 116.0;
+    RGB_FROM_XYZ * Vector3::new(c[1] / 500.0 + y, y, y - c[2] / 200.0).component_mul(&white())
+}
+
+// CIELAB with a and b scaled by the lightness, which Hunt observed is how colorful dark colors look
+fn hunt_lab(rgb: &Vector3<f64>) -> Vector3<f64> {
+    let f = |t: f64| if t > (6.0f64 / 29.0).powi(3) { t.cbrt() } else { t / (3.0 * (6.0f64 / 29.0).powi(2)) + 4.0 / 29.0 };
+    let v = (XYZ_FROM_RGB * rgb).component_div(&white()).map(f);
+    let l = 116.0 * v[1] - 16.0;
+    Vector3::new(l, 0.01 * l * 500.0 * (v[0] - v[1]), 0.01 * l * 200.0 * (v[1] - v[2]))
+}
+
+fn hyab(a: &Vector3<f64>, b: &Vector3<f64>) -> f64 {
+    (a[0] - b[0]).abs() + ((a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+}
+
+fn flip(test: &Image, reference: &Image) -> Plane {
+    const QC: f64 = 0.7;
+    const QF: f64 = 0.5;
+    const PC: f64 = 0.4;
+    const PT: f64 = 0.95;
+    // width of the edge and point detectors, in degrees
+    const FEATURE_WIDTH: f64 = 0.082;
+
+    // the contrast sensitivity of each of the opponent channels, as a sum of two gaussians (a1, b1, a2, b2)
+    const CSF: [[f64; 4]; 3] = [
+        [1.0, 0.0047, 0.0, 1e-5],
+        [1.0, 0.0053, 0.0, 1e-5],
+        [34.1, 0.04, 13.5, 0.025],
+    ];
+    let r = (3.0 * (0.04 / (2.0 * PI * PI)).sqrt() * PIXELS_PER_DEGREE).ceil() as isize;
+    let csf = |p: &Plane, c: [f64; 4]| -> Plane {
+        let mut out = vec![vec![0.0; p[0].len()]; p.len()];
+        let mut total = 0.0;
+        for (a, b) in [(c[0], c[1]), (c[2], c[3])] {
+            if a == 0.0 { continue }
+            let k: Vec<f64> = (-r..=r).map(|x| (-PI * PI * (x as f64 / PIXELS_PER_DEGREE).powi(2) / b).exp()).collect();
+            let w = a * (PI / b).sqrt();
+            total += w * k.iter().sum::<f64>().powi(2);
+            out = zip(&out, &convolve(p, &k, &k), |o, v| o + w * v);
+        }
+        out.iter().map(|r| r.iter().map(|v| v / total).collect()).collect()
+    };
+
+    let sd = 0.5 * FEATURE_WIDTH * PIXELS_PER_DEGREE;
+    let fr = (3.0 * sd).ceil() as isize;
+    let g: Vec<f64> = (-fr..=fr).map(|x| (-(x * x) as f64 / (2.0 * sd * sd)).exp()).collect();
+    let gn: Vec<f64> = g.iter().map(|v| v / g.iter().sum::<f64>()).collect();
+    // first and second derivatives, with the positive and negative weights each adding up to 1
+    let normalize = |k: Vec<f64>| -> Vec<f64> {
+        let pos: f64 = k.iter().filter(|v| **v > 0.0).sum();
+        let neg: f64 = -k.iter().filter(|v| **v < 0.0).sum::<f64>();
+        k.iter().map(|v| if *v > 0.0 { v / pos } else { v / neg }).collect()
+    };
+    let edge = normalize((-fr..=fr).zip(&g).map(|(x, g)| -x as f64 * g).collect());
+    let point = normalize((-fr..=fr).zip(&g).map(|(x, g)| ((x * x) as f64 / (sd * sd) - 1.0) * g).collect());
+
+    let prepare = |img: &Image| {
+        let c: Image = img.iter().map(|r| r.iter().map(ycxcz).collect()).collect();
+        let filtered: Vec<Plane> = (0..3).map(|i| csf(&plane(&c, |v| v[i]), CSF[i])).collect();
+        let lab: Image = (0..c.len()).map(|y| (0..c[0].len()).map(|x| {
+            let v = Vector3::new(filtered[0][y][x], filtered[1][y][x], filtered[2][y][x]);
+            hunt_lab(&rgb_from_ycxcz(&v).map(|c| c.clamp(0.0, 1.0)))
+        }).collect()).collect();
+
+        let lum = plane(&c, |v| (v[0] + 16.0) / 116.0);
+        let magnitude = |k: &[f64]| zip(&convolve(&lum, k, &gn), &convolve(&lum, &gn, k), |a, b| a.hypot(b));
+        (lab, magnitude(&edge), magnitude(&point))
+    };
+    let (tl, te, tp) = prepare(test);
+    let (rl, re, rp) = prepare(reference);
+
+    let cmax = hyab(&hunt_lab(&Vector3::new(0.0, 1.0, 0.0)), &hunt_lab(&Vector3::new(0.0, 0.0, 1.0))).powf(QC);
+    (0..tl.len()).map(|y| (0..tl[0].len()).map(|x| {
+        let e = hyab(&tl[y][x], &rl[y][x]).powf(QC);
+        // small differences are squeezed into most of the range, so they stay visible
+        let color = if e < PC * cmax { PT / (PC * cmax) * e } else { PT + (e - PC * cmax) / (cmax - PC * cmax) * (1.0 - PT) };
+        let feature = ((te[y][x] - re[y][x]).abs().max((tp[y][x] - rp[y][x]).abs()) / 2f64.sqrt()).powf(QF);
+        color.powf(1.0 - feature)
+    }).collect()).collect()
+}
+
+pub fn print(m: &Metrics) {
+    for (n, v) in METRICS.iter().zip(m.values()) {
+        println!("{n:<7} {v:.6}");
+    }
+}
+
+// a per pixel error in 0..1 as a heat map
+pub fn save_diff(path: &Path, err: &Plane) -> Result<(), Box<dyn std::error::Error>> {
+    let img: Image = err.iter().map(|r| r.iter().map(|e| crate::renderer::heatmap(*e)).collect()).collect();
+    crate::output::save_png(path, &crate::renderer::to_rgb8(&img))
+}
+
+// renders `passes` passes of the scene at the reference's size, and measures the error after 1, 2, 4, ... of them
+// and after the last one
+pub fn convergence(rs: &RendererState, reference: &[Vec<Vector3<f64>>], passes: usize) -> Result<Vec<(usize, Metrics)>, Box<dyn std::error::Error>> {
+    let (w, h) = (reference.first().map_or(0, |r| r.len()), reference.len());
+    let tiles = crate::renderer::tiles(w, h);
+    let mut img = vec![vec![Pixel::default(); w]; h];
+
+    println!("spp,{}", METRICS.join(","));
+    let mut out = Vec::new();
+    for pass in 1..=passes {
+        crate::renderer::render_tiles(rs, w, h, &mut img, pass, &tiles);
+        if pass.is_power_of_two() || pass == passes {
+            let spp = pass * crate::renderer::SAMPLES_LVL;
+            let (m, _) = compare(&crate::renderer::layer(&img, crate::renderer::Aov::Beauty), reference)?;
+            println!("{spp},{}", m.values().map(|v| v.to_string()).join(","));
+            out.push((spp, m));
+        }
+    }
+    Ok(out)
+}
+
+// error against samples on log-log axes, every metric scaled to its first value so they fit together,
+// with the 1/sqrt(n) that plain Monte Carlo noise goes down by as a guide
+pub fn plot(path: &Path, points: &[(usize, Metrics)]) -> Result<(), Box<dyn std::error::Error>> {
+    const W: f64 = 640.0;
+    const H: f64 = 400.0;
+    const MARGIN: f64 = 50.0;
+    const COLORS: [&str; 4] = ["#d62728", "#1f77b4", "#2ca02c", "#9467bd"];
+
+    let Some(first) = points.first() else { return Err("nothing to plot".into()) };
+    // as errors, where lower is better
+    let series: Vec<(&str, Vec<f64>)> = vec![
+        ("rmse", points.iter().map(|p| p.1.rmse).collect()),
+        ("relmse", points.iter().map(|p| p.1.relmse).collect()),
+        ("1 - ssim", points.iter().map(|p| 1.0 - p.1.ssim).collect()),
+        ("flip", points.iter().map(|p| p.1.flip).collect()),
+    ];
+    let guide: Vec<f64> = points.iter().map(|p| (first.0 as f64 / p.0 as f64).sqrt()).collect();
+
+    let rel: Vec<Vec<f64>> = series.iter().map(|(_, v)| v.iter().map(|e| e / v[0].max(1e-20)).collect()).collect();
+    let lo = rel.iter().flatten().chain(&guide).copied().filter(|v| *v > 0.0).fold(1.0, f64::min).log10().floor();
+    let (x0, x1) = ((first.0 as f64).log2(), (points.last().unwrap().0 as f64).log2().max((first.0 as f64).log2() + 1.0));
+    let px = |spp: usize| MARGIN + ((spp as f64).log2() - x0) / (x1 - x0) * (W - 2.0 * MARGIN);
+    let py = |v: f64| MARGIN + (0.0 - v.max(1e-20).log10()) / (0.0 - lo).max(1.0) * (H - 2.0 * MARGIN);
+
+    let mut s = format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{W}\" height=\"{H}\" font-family=\"sans-serif\" font-size=\"11\">\n");
+    s += &format!("<rect width=\"{W}\" height=\"{H}\" fill=\"white\"/>\n");
+    for (spp, _) in points {
+        s += &format!("<line x1=\"{0}\" y1=\"{MARGIN}\" x2=\"{0}\" y2=\"{1}\" stroke=\"#ddd\"/>\n", px(*spp), H - MARGIN);
+        s += &format!("<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">{spp}</text>\n", px(*spp), H - MARGIN + 15.0);
+    }
+    for d in (lo as i32)..=0 {
+        let y = py(10f64.powi(d));
+        s += &format!("<line x1=\"{MARGIN}\" y1=\"{y}\" x2=\"{}\" y2=\"{y}\" stroke=\"#ddd\"/>\n", W - MARGIN);
+        s += &format!("<text x=\"{}\" y=\"{}\" text-anchor=\"end\">1e{d}</text>\n", MARGIN - 5.0, y + 4.0);
+    }
+    s += &format!("<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">samples per pixel</text>\n", W / 2.0, H - 10.0);
+    s += &format!("<text x=\"{MARGIN}\" y=\"{}\">error relative to {} spp</text>\n", MARGIN - 20.0, first.0);
+
+    let line = |v: &[f64], style: &str| {
+        let p: Vec<String> = points.iter().zip(v).map(|(p, v)| format!("{},{}", px(p.0), py(*v))).collect();
+        format!("<polyline points=\"{}\" fill=\"none\" {style}/>\n", p.join(" "))
+    };
+    s += &line(&guide, "stroke=\"#999\" stroke-dasharray=\"4 4\"");
+    for (i, ((name, _), v)) in series.iter().zip(&rel).enumerate() {
+        s += &line(v, &format!("stroke=\"{}\" stroke-width=\"2\"", COLORS[i]));
+        let y = MARGIN + 15.0 * i as f64;
+        s += &format!("<text x=\"{}\" y=\"{y}\" fill=\"{}\">{name}</text>\n", W - MARGIN - 60.0, COLORS[i]);
+    }
+    s += &format!("<text x=\"{}\" y=\"{}\" fill=\"#999\">1/sqrt(n)</text>\n", W - MARGIN - 60.0, MARGIN + 15.0 * series.len() as f64);
+    s += "</svg>\n";
+
+    std::fs::write(path, s)?;
+    Ok(())
+}
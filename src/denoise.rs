@@ -0,0 +1,82 @@
+use nalgebra::base::*;
+use rayon::prelude::*;
+
+use crate::renderer::{Aov, Pixel, layer};
+
+pub const MAX_STRENGTH: f64 = 4.0;
+
+// B3 spline, the taps of every level are this far apart times 2^level
+const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+// how much two pixels are allowed to differ before they stop being averaged together
+const COLOR_SIGMA: f64 = 0.6;
+const NORMAL_POWER: f64 = 64.0;
+const DEPTH_SIGMA: f64 = 0.05;
+const ALBEDO_SIGMA: f64 = 0.1;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Settings {
+    // each level doubles the radius, 5 covers about 60 pixels
+    pub levels: usize,
+    // scales how different colors can be and still get blurred together
+    pub strength: f64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self { levels: 4, strength: 1.0 }
+    }
+}
+
+// edge-avoiding à-trous wavelet filter: blurs the beauty pass but stops at edges in the normal, depth and albedo buffers
+pub fn denoise(img: &[Vec<Pixel>], s: &Settings) -> Vec<Vec<Vector3<f64>>> {
+    let mut color = layer(img, Aov::Beauty);
+    let normal = layer(img, Aov::Normal);
+    let depth = layer(img, Aov::Depth);
+    let albedo = layer(img, Aov::Albedo);
+
+    let h = color.len();
+    let w = color.first().map_or(0, |r| r.len());
+
+    for level in 0..s.levels {
+        let step = 1 << level;
+        // later levels only smooth what's left, so their color tolerance shrinks
+        let sigma = COLOR_SIGMA * s.strength / (1 << level) as f64;
+
+        color = (0..h).into_par_iter().map(|y| (0..w).map(|x| {
+            let (cp, np, zp, ap) = (color[y][x], normal[y][x], depth[y][x][0], albedo[y][x]);
+
+            let mut sum = Vector3::default();
+            let mut total = 0.0;
+            for (j, ky) in KERNEL.iter().enumerate() {
+                let qy = y as isize + (j as isize - 2) * step;
+                if qy < 0 || qy >= h as isize { continue }
+                for (i, kx) in KERNEL.iter().enumerate() {
+                    let qx = x as isize + (i as isize - 2) * step;
+                    if qx < 0 || qx >= w as isize { continue }
+                    let (qy, qx) = (qy as usize, qx as usize);
+
+                    let cq = color[qy][qx];
+                    let wc = (-(cp - cq).norm_squared() / (sigma * sigma).max(1e-12)).exp();
+
+                    let (nq, zq, aq) = (normal[qy][qx], depth[qy][qx][0], albedo[qy][qx]);
+                    // pixels that only saw the sky have no normal
+                    let wn = match (np.try_normalize(1e-3), nq.try_normalize(1e-3)) {
+                        (Some(a), Some(b)) => a.dot(&b).max(0.0).powf(NORMAL_POWER),
+                        (None, None) => 1.0,
+                        _ => 0.0,
+                    };
+                    let wz = (-(zp - zq).abs() / (DEPTH_SIGMA * zp.abs().max(zq.abs()) + 1e-3)).exp();
+                    let wa = (-(ap - aq).norm_squared() / (ALBEDO_SIGMA * ALBEDO_SIGMA)).exp();
+
+                    let wt = kx * ky * wc * wn * wz * wa;
+                    sum += cq * wt;
+                    total += wt;
+                }
+            }
+            sum / total.max(1e-12)
+        }).collect()).collect();
+    }
+
+    color
+}
@@ -0,0 +1,82 @@
+use std::f64::consts::TAU;
+
+// how much a sample counts towards a pixel, by its distance from the pixel's middle
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Filter {
+    // only the pixel a sample lands in, the cheapest and the sharpest
+    #[default]
+    Box,
+    Tent,
+    Gaussian,
+    // B = C = 1/3, sharpens a little with its negative lobes
+    Mitchell,
+    BlackmanHarris,
+}
+
+pub const FILTERS: &[(Filter, &str)] = &[
+    (Filter::Box,            "box"),
+    (Filter::Tent,           "tent"),
+    (Filter::Gaussian,       "gaussian"),
+    (Filter::Mitchell,       "mitchell"),
+    (Filter::BlackmanHarris, "blackman_harris"),
+];
+
+const GAUSSIAN_SIGMA: f64 = 0.5;
+
+impl Filter {
+    pub fn name(&self) -> &'static str {
+        FILTERS.iter().find(|f| f.0 == *self).unwrap().1
+    }
+
+    pub fn parse(s: &str) -> Result<Self, String> {
+        FILTERS.iter().find(|f| f.1 == s).map(|f| f.0).ok_or_else(|| {
+            format!("unknown filter `{s}`, expected one of {}", FILTERS.iter().map(|f| f.1).collect::<Vec<_>>().join(", "))
+        })
+    }
+
+    // in pixels, samples further away than this don't count
+    pub fn radius(&self) -> f64 {
+        match self {
+            Filter::Box => 0.5,
+            Filter::Tent => 1.0,
+            Filter::Gaussian => 1.5,
+            Filter::Mitchell | Filter::BlackmanHarris => 2.0,
+        }
+    }
+
+    // separable, so the weight of a sample is the product of both axes
+    pub fn weight(&self, dx: f64, dy: f64) -> f64 {
+        self.eval(dx) * self.eval(dy)
+    }
+
+    fn eval(&self, x: f64) -> f64 {
+        let r = self.radius();
+        let x = x.abs();
+        if x > r { return 0.0 }
+
+        match self {
+            Filter::Box => 1.0,
+            Filter::Tent => 1.0 - x / r,
+            Filter::Gaussian => {
+                // shifted down so it reaches 0 at the radius instead of stopping abruptly
+                let g = |x: f64| (-x * x / (2.0 * GAUSSIAN_SIGMA * GAUSSIAN_SIGMA)).exp();
+                (g(x) - g(r)).max(0.0)
+            },
+            Filter::Mitchell => {
+                const B: f64 = 1.0 / 3.0;
+                const C: f64 = 1.0 / 3.0;
+                let x = x * 2.0 / r;
+                let v = if x < 1.0 {
+                    (12.0 - 9.0 * B - 6.0 * C) * x * x * x + (-18.0 + 12.0 * B + 6.0 * C) * x * x + (6.0 - 2.0 * B)
+                } else {
+                    (-B - 6.0 * C) * x * x * x + (6.0 * B + 30.0 * C) * x * x + (-12.0 * B - 48.0 * C) * x + (8.0 * B + 24.0 * C)
+                };
+                v / 6.0
+            },
+            Filter::BlackmanHarris => {
+                let t = (x + r) / (2.0 * r);
+                0.35875 - 0.48829 * (TAU * t).cos() + 0.14128 * (2.0 * TAU * t).cos() - 0.01168 * (3.0 * TAU * t).cos()
+            },
+        }
+    }
+}
@@ -0,0 +1,293 @@
+// the ray against each kind of object on its own: a few cases worked out by hand, and random ones checked
+// against what has to hold for every ray
+use nalgebra::base::*;
+use rand::Rng;
+
+use crate::renderer::{HitInfo, Mesh, ObjectKind, Ray, Sphere, Triangle, T_MIN};
+use crate::rng::Pcg;
+
+// random rays per property
+const CASES: usize = 10_000;
+const EPS: f64 = 1e-6;
+
+fn v(x: f64, y: f64, z: f64) -> Vector3<f64> {
+    Vector3::new(x, y, z)
+}
+
+fn ray(o: Vector3<f64>, d: Vector3<f64>) -> Ray {
+    Ray::new(o, d)
+}
+
+fn sphere(c: Vector3<f64>, r: f64) -> Sphere {
+    Sphere { c, r }
+}
+
+fn triangle(a: Vector3<f64>, b: Vector3<f64>, c: Vector3<f64>) -> Triangle {
+    Triangle { vp: [a, b, c], vn: None }
+}
+
+// facing -z, towards a camera in front of it
+fn facing() -> Triangle {
+    triangle(v(-1.0, -1.0, 0.0), v(0.0, 1.0, 0.0), v(1.0, -1.0, 0.0))
+}
+
+fn is_miss(h: &HitInfo) -> bool {
+    h.t == HitInfo::default().t
+}
+
+fn close(a: Vector3<f64>, b: Vector3<f64>, eps: f64) -> bool {
+    (a - b).norm() <= eps * (1.0 + b.norm())
+}
+
+fn unit(rng: &mut Pcg) -> Vector3<f64> {
+    loop {
+        let p = v(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+        if p.norm_squared() > 1e-6 && p.norm_squared() <= 1.0 { return p.normalize() }
+    }
+}
+
+fn point(rng: &mut Pcg, size: f64) -> Vector3<f64> {
+    v(rng.gen_range(-size..size), rng.gen_range(-size..size), rng.gen_range(-size..size))
+}
+
+#[test]
+fn sphere_hit() {
+    let h = sphere(v(0.0, 0.0, 5.0), 1.0).try_ray(&ray(v(0.0, 0.0, 0.0), v(0.0, 0.0, 1.0)));
+    assert!((h.t - 4.0).abs() < EPS, "t {}", h.t);
+    assert!(close(h.p, v(0.0, 0.0, 4.0), EPS));
+    assert!(close(h.n, v(0.0, 0.0, -1.0), EPS));
+
+    // t is in units of the direction's length
+    let h = sphere(v(0.0, 0.0, 5.0), 1.0).try_ray(&ray(v(0.0, 0.0, 0.0), v(0.0, 0.0, 2.0)));
+    assert!((h.t - 2.0).abs() < EPS, "t {}", h.t);
+}
+
+#[test]
+fn sphere_miss() {
+    let s = sphere(v(0.0, 0.0, 5.0), 1.0);
+    assert!(is_miss(&s.try_ray(&ray(v(0.0, 1.5, 0.0), v(0.0, 0.0, 1.0)))));
+    // behind the origin
+    assert!(is_miss(&s.try_ray(&ray(v(0.0, 0.0, 0.0), v(0.0, 0.0, -1.0)))));
+    assert!(is_miss(&s.try_ray(&ray(v(0.0, 0.0, 10.0), v(0.0, 0.0, 1.0)))));
+}
+
+#[test]
+fn sphere_grazing() {
+    let s = sphere(v(0.0, 0.0, 5.0), 1.0);
+    let h = s.try_ray(&ray(v(1.0, 0.0, 0.0), v(0.0, 0.0, 1.0)));
+    assert!((h.t - 5.0).abs() < EPS, "t {}", h.t);
+    assert!(close(h.n, v(1.0, 0.0, 0.0), EPS));
+    assert!(is_miss(&s.try_ray(&ray(v(1.0 + 1e-9, 0.0, 0.0), v(0.0, 0.0, 1.0)))));
+}
+
+// the near root is behind the origin, so it's the far one
+#[test]
+fn sphere_from_inside() {
+    let s = sphere(v(0.0, 0.0, 5.0), 2.0);
+    let h = s.try_ray(&ray(v(0.0, 0.0, 5.0), v(1.0, 0.0, 0.0)));
+    assert!((h.t - 2.0).abs() < EPS, "t {}", h.t);
+    assert!(close(h.p, v(2.0, 0.0, 5.0), EPS));
+    // still pointing outwards
+    assert!(close(h.n, v(1.0, 0.0, 0.0), EPS));
+
+    let h = s.try_ray(&ray(v(0.0, 0.0, 4.0), v(0.0, 0.0, 1.0)));
+    assert!((h.t - 3.0).abs() < EPS, "t {}", h.t);
+}
+
+// a ray leaving the surface doesn't hit it again where it starts, but does on the other side when it goes in
+#[test]
+fn sphere_from_surface() {
+    let s = sphere(v(0.0, 0.0, 0.0), 1.0);
+    assert!(is_miss(&s.try_ray(&ray(v(0.0, 1.0, 0.0), v(0.0, 1.0, 0.0)))));
+    assert!(is_miss(&s.try_ray(&ray(v(0.0, 1.0, 0.0), v(1.0, 0.0, 0.0)))));
+    let h = s.try_ray(&ray(v(0.0, 1.0, 0.0), v(0.0, -1.0, 0.0)));
+    assert!((h.t - 2.0).abs() < EPS, "t {}", h.t);
+}
+
+#[test]
+fn triangle_hit() {
+    let h = facing().try_ray(&ray(v(0.2, -0.1, -3.0), v(0.0, 0.0, 1.0)));
+    assert!((h.t - 3.0).abs() < EPS, "t {}", h.t);
+    assert!(close(h.p, v(0.2, -0.1, 0.0), EPS));
+    assert!(close(h.n, v(0.0, 0.0, -1.0), EPS));
+
+    // on a vertex and an edge
+    assert!(facing().try_ray(&ray(v(0.0, 1.0, -3.0), v(0.0, 0.0, 1.0))).hit());
+    assert!(facing().try_ray(&ray(v(0.0, -1.0, -3.0), v(0.0, 0.0, 1.0))).hit());
+}
+
+#[test]
+fn triangle_miss() {
+    let t = facing();
+    assert!(is_miss(&t.try_ray(&ray(v(0.9, 0.9, -3.0), v(0.0, 0.0, 1.0)))));
+    assert!(is_miss(&t.try_ray(&ray(v(0.0, -1.1, -3.0), v(0.0, 0.0, 1.0)))));
+    // behind the origin
+    assert!(is_miss(&t.try_ray(&ray(v(0.0, 0.0, 3.0), v(0.0, 0.0, 1.0)))));
+}
+
+// single sided, the scene files count on it
+#[test]
+fn triangle_back_face() {
+    assert!(is_miss(&facing().try_ray(&ray(v(0.0, 0.0, 3.0), v(0.0, 0.0, -1.0)))));
+}
+
+#[test]
+fn triangle_parallel() {
+    let t = facing();
+    // in its plane, through the middle of it
+    assert!(is_miss(&t.try_ray(&ray(v(0.0, 0.0, 0.0), v(1.0, 0.0, 0.0)))));
+    assert!(is_miss(&t.try_ray(&ray(v(-3.0, 0.0, 0.0), v(1.0, 0.0, 0.0)))));
+    // so close to parallel that t would be far beyond the triangle or not a number
+    assert!(is_miss(&t.try_ray(&ray(v(-3.0, 0.0, -1e-20), v(1.0, 0.0, 1e-15)))));
+}
+
+#[test]
+fn triangle_degenerate() {
+    let o = v(0.0, 0.0, -3.0);
+    for t in [
+        triangle(v(0.0, 0.0, 0.0), v(0.0, 0.0, 0.0), v(0.0, 0.0, 0.0)),
+        triangle(v(-1.0, 0.0, 0.0), v(0.0, 0.0, 0.0), v(1.0, 0.0, 0.0)),
+        triangle(v(-1.0, -1.0, 0.0), v(-1.0, -1.0, 0.0), v(1.0, 1.0, 0.0)),
+    ] {
+        for d in [v(0.0, 0.0, 1.0), v(0.3, 0.1, 1.0), v(1.0, 0.0, 1.0)] {
+            let h = t.try_ray(&ray(o, d));
+            assert!(is_miss(&h), "{t:?} hit at {}", h.t);
+        }
+    }
+}
+
+#[test]
+fn triangle_normals() {
+    let mut t = facing();
+    t.vn = Some([v(-1.0, 0.0, -1.0), v(0.0, 1.0, -1.0), v(1.0, 0.0, -1.0)]);
+    let h = t.try_ray(&ray(v(0.3, -0.2, -3.0), v(0.0, 0.0, 1.0)));
+    assert!(h.hit());
+    assert!((h.n.norm() - 1.0).abs() < EPS, "normal of length {}", h.n.norm());
+}
+
+#[test]
+fn mesh() {
+    let far = triangle(v(-1.0, -1.0, 2.0), v(0.0, 1.0, 2.0), v(1.0, -1.0, 2.0));
+    let m = Mesh { ts: vec![far, facing()] };
+    let h = m.try_ray(&ray(v(0.0, 0.0, -3.0), v(0.0, 0.0, 1.0)));
+    assert!((h.t - 3.0).abs() < EPS, "t {}", h.t);
+    // the same miss as everything else
+    assert!(is_miss(&m.try_ray(&ray(v(0.0, 0.0, -3.0), v(0.0, 0.0, -1.0)))));
+    assert!(is_miss(&Mesh { ts: Vec::new() }.try_ray(&ray(v(0.0, 0.0, 0.0), v(0.0, 0.0, 1.0)))));
+}
+
+// a random ray against a random sphere hits it at the first of the two points where its line crosses it that's in front,
+// with the normal pointing outwards, and misses when the line passes it by
+#[test]
+fn sphere_properties() {
+    let mut rng = Pcg::new(1, 0);
+    for _ in 0..CASES {
+        let scale = 10f64.powf(rng.gen_range(-2.0..3.0));
+        let s = sphere(point(&mut rng, scale * 10.0), scale * rng.gen_range(0.1..2.0));
+        let o = s.c + unit(&mut rng) * s.r * rng.gen_range(0.5..20.0);
+        let d = unit(&mut rng) * rng.gen_range(0.1..10.0);
+        let h = s.try_ray(&ray(o, d));
+
+        // where the line gets closest to the middle, how close, and where it goes in and out
+        let along = (s.c - o).dot(&d) / d.norm_squared();
+        let miss = (o + d * along - s.c).norm();
+        if (miss - s.r).abs() < 1e-6 * s.r { continue }
+        let half = (s.r * s.r - miss * miss).max(0.0).sqrt() / d.norm();
+        let (near, far) = (along - half, along + half);
+        if [near, far].iter().any(|t| (t - T_MIN).abs() < 1e-6 * along.abs().max(1.0)) { continue }
+        let expect = [near, far].into_iter().find(|t| miss < s.r && *t > T_MIN);
+
+        assert_eq!(h.hit(), expect.is_some(), "{o} {d} against {} {}: t {}", s.c, s.r, h.t);
+        if let Some(t) = expect {
+            assert!((h.t - t).abs() < 1e-6 * t.max(1.0), "t {} instead of {t}", h.t);
+            assert!(close(h.p, o + d * h.t, 1e-9));
+            assert!(((h.p - s.c).norm() - s.r).abs() < 1e-6 * s.r);
+            assert!((h.n.norm() - 1.0).abs() < EPS);
+            assert_eq!(h.n.dot(&d) < 0.0, t == near, "the normal points {}", if t == near { "inwards" } else { "outwards" });
+        } else {
+            assert!(is_miss(&h));
+        }
+    }
+}
+
+// from anywhere inside, every direction hits the far side
+#[test]
+fn sphere_inside_properties() {
+    let mut rng = Pcg::new(2, 0);
+    for _ in 0..CASES {
+        let s = sphere(point(&mut rng, 100.0), rng.gen_range(0.1..50.0));
+        let o = s.c + unit(&mut rng) * s.r * rng.gen_range(0.0..0.99);
+        let d = unit(&mut rng);
+        let h = s.try_ray(&ray(o, d));
+
+        assert!(h.hit(), "{o} {d} inside {} {}", s.c, s.r);
+        assert!(((h.p - s.c).norm() - s.r).abs() < 1e-6 * s.r);
+        assert!(h.n.dot(&d) > 0.0);
+    }
+}
+
+// a ray through a random point of a random triangle hits it there from the front and misses it from the back,
+// and one through a point outside misses from both sides, at any size
+#[test]
+fn triangle_properties() {
+    let mut rng = Pcg::new(3, 0);
+    for _ in 0..CASES {
+        let scale = 10f64.powf(rng.gen_range(-3.0..3.0));
+        let t = triangle(point(&mut rng, scale), point(&mut rng, scale), point(&mut rng, scale));
+        let n = (t.vp[1] - t.vp[0]).cross(&(t.vp[2] - t.vp[0]));
+        // too thin to say which side of an edge a point is on
+        if n.norm() < 1e-3 * scale * scale { continue }
+        let n = n.normalize();
+
+        let (u, w) = (rng.gen_range(0.0..1.0), rng.gen_range(0.0..1.0));
+        let (u, w) = if u + w > 1.0 { (1.0 - u, 1.0 - w) } else { (u, w) };
+        let inside = u > 0.01 && w > 0.01 && u + w < 0.99;
+        let outside = rng.gen_bool(0.5);
+        // past one of the edges by a bit
+        let (u, w) = if outside { (u, -w - 0.01) } else { (u, w) };
+        if !outside && !inside { continue }
+        let target = t.vp[0] + (t.vp[1] - t.vp[0]) * u + (t.vp[2] - t.vp[0]) * w;
+
+        // from the front, at any angle steeper than grazing
+        let side = loop {
+            let side = unit(&mut rng);
+            if side.dot(&n) > 0.05 { break side }
+        };
+        let dist = scale * rng.gen_range(0.1..10.0);
+        let front = target + side * dist;
+        let h = t.try_ray(&ray(front, target - front));
+        if outside {
+            assert!(is_miss(&h), "{:?} hit outside at {}", t.vp, h.t);
+        } else {
+            assert!(h.hit() && (h.t - 1.0).abs() < 1e-6, "{:?} from {front}: t {}", t.vp, h.t);
+            assert!(close(h.p, target, 1e-6));
+            assert!(close(h.n, n, 1e-9));
+        }
+
+        let back = target - side * dist;
+        assert!(is_miss(&t.try_ray(&ray(back, target - back))), "{:?} hit from behind", t.vp);
+    }
+}
+
+// whatever the ray and the triangle, it's a miss or a hit in front of the origin, never something that isn't a number
+#[test]
+fn triangle_any_ray() {
+    let mut rng = Pcg::new(4, 0);
+    for i in 0..CASES {
+        let mut vp = [point(&mut rng, 1.0), point(&mut rng, 1.0), point(&mut rng, 1.0)];
+        // every so often two corners in the same place, or all three on a line
+        match i % 4 {
+            0 => vp[1] = vp[0],
+            1 => vp[2] = vp[0] + (vp[1] - vp[0]) * rng.gen_range(-2.0..2.0),
+            _ => (),
+        }
+        let t = triangle(vp[0], vp[1], vp[2]);
+        let h = t.try_ray(&ray(point(&mut rng, 3.0), point(&mut rng, 1.0)));
+
+        if i % 4 < 2 {
+            assert!(is_miss(&h), "{vp:?} has no area but was hit at {}", h.t);
+        }
+        assert!(is_miss(&h) || (h.t > T_MIN && h.t.is_finite() && h.p.iter().all(|c| c.is_finite()) && (h.n.norm() - 1.0).abs() < EPS),
+            "{vp:?}: t {} p {} n {}", h.t, h.p, h.n);
+    }
+}
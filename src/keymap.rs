@@ -0,0 +1,282 @@
+use crossterm::event::{KeyCode, KeyModifiers};
+use std::collections::HashMap;
+use std::path::Path;
+use std::f64::consts::TAU;
+
+pub const KEYMAP_PATH: &str = "keymap.cfg";
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Action {
+    Quit,
+    Screenshot,
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    MoveForward,
+    MoveBack,
+    TurnUp,
+    TurnDown,
+    TurnLeft,
+    TurnRight,
+    FocusFar,
+    FocusNear,
+    AutoFocus,
+    ApertureUp,
+    ApertureDown,
+    Mark,
+    Record,
+    Play,
+    Hud,
+    Help,
+    Pick,
+    FieldPrev,
+    FieldNext,
+    ValueUp,
+    ValueDown,
+    SaveScene,
+    Outliner,
+    SelectPrev,
+    SelectNext,
+    ToggleVisible,
+    ToggleShadow,
+    ToggleLight,
+    TransformMode,
+    SaveCheckpoint,
+    CycleBuffer,
+    Denoise,
+    DenoiseWeaker,
+    DenoiseStronger,
+    ExposureUp,
+    ExposureDown,
+    Tonemap,
+    WhiteBalanceUp,
+    WhiteBalanceDown,
+    Bloom,
+    Vignette,
+    ChromaticAberration,
+    Grain,
+    Sampler,
+    Filter,
+}
+
+// name in the config file, and the keys it is bound to when the config doesn't say otherwise
+pub const ACTIONS: &[(Action, &str, &[&str])] = &[
+    (Action::Quit,         "quit",          &["esc"]),
+    (Action::Screenshot,   "screenshot",    &["f12"]),
+    (Action::MoveLeft,     "move_left",     &["a"]),
+    (Action::MoveRight,    "move_right",    &["d"]),
+    (Action::MoveUp,       "move_up",       &["q"]),
+    (Action::MoveDown,     "move_down",     &["e"]),
+    (Action::MoveForward,  "move_forward",  &["w"]),
+    (Action::MoveBack,     "move_back",     &["s"]),
+    (Action::TurnUp,       "turn_up",       &["up"]),
+    (Action::TurnDown,     "turn_down",     &["down"]),
+    (Action::TurnLeft,     "turn_left",     &["left"]),
+    (Action::TurnRight,    "turn_right",    &["right"]),
+    (Action::FocusFar,     "focus_far",     &["home"]),
+    (Action::FocusNear,    "focus_near",    &["end"]),
+    (Action::AutoFocus,    "auto_focus",    &["backspace"]),
+    (Action::ApertureUp,   "aperture_up",   &["pageup"]),
+    (Action::ApertureDown, "aperture_down", &["pagedown"]),
+    (Action::Mark,         "mark",          &["m"]),
+    (Action::Record,       "record",        &["r"]),
+    (Action::Play,         "play",          &["p"]),
+    (Action::Hud,          "hud",           &["h"]),
+    (Action::Help,         "help",          &["f1", "?"]),
+    (Action::Pick,         "pick",          &["enter"]),
+    (Action::FieldPrev,    "field_prev",    &["["]),
+    (Action::FieldNext,    "field_next",    &["]"]),
+    (Action::ValueUp,      "value_up",      &["=", "+"]),
+    (Action::ValueDown,    "value_down",    &["-"]),
+    (Action::SaveScene,    "save_scene",    &["f2"]),
+    (Action::Outliner,     "outliner",      &["o"]),
+    (Action::SelectPrev,   "select_prev",   &["k"]),
+    (Action::SelectNext,   "select_next",   &["j"]),
+    (Action::ToggleVisible, "toggle_visible", &["v"]),
+    (Action::ToggleShadow, "toggle_shadow", &["c"]),
+    (Action::ToggleLight,  "toggle_light",  &["l"]),
+    (Action::TransformMode, "transform_mode", &["t"]),
+    (Action::SaveCheckpoint, "save_checkpoint", &["f3"]),
+    (Action::CycleBuffer,  "cycle_buffer",  &["b"]),
+    (Action::Denoise,      "denoise",       &["n"]),
+    (Action::DenoiseWeaker, "denoise_weaker", &[","]),
+    (Action::DenoiseStronger, "denoise_stronger", &["."]),
+    (Action::ExposureUp,   "exposure_up",   &["u"]),
+    (Action::ExposureDown, "exposure_down", &["y"]),
+    (Action::Tonemap,      "tonemap",       &["g"]),
+    (Action::WhiteBalanceUp, "white_balance_up", &["x"]),
+    (Action::WhiteBalanceDown, "white_balance_down", &["z"]),
+    (Action::Bloom,        "bloom",         &["f5"]),
+    (Action::Vignette,     "vignette",      &["f6"]),
+    (Action::ChromaticAberration, "chromatic_aberration", &["f7"]),
+    (Action::Grain,        "grain",         &["f8"]),
+    (Action::Sampler,      "sampler",       &["i"]),
+    (Action::Filter,       "filter",        &["f"]),
+];
+
+impl Action {
+    // continuous actions keep going while their key is held, the rest fire once per press
+    pub fn continuous(&self) -> bool {
+        matches!(self,
+            Action::MoveLeft | Action::MoveRight | Action::MoveUp | Action::MoveDown
+                | Action::MoveForward | Action::MoveBack
+                | Action::TurnUp | Action::TurnDown | Action::TurnLeft | Action::TurnRight
+                | Action::FocusFar | Action::FocusNear
+                | Action::ApertureUp | Action::ApertureDown
+                | Action::ValueUp | Action::ValueDown
+        )
+    }
+}
+
+pub struct Keymap {
+    pub keys: HashMap<KeyCode, Action>,
+
+    pub move_step: f64,
+    pub turn_step: f64,
+    pub focus_step: f64,
+    pub aperture_step: f64,
+    // steps per second while a key is held
+    pub hold_rate: f64,
+
+    pub fast_modifier: KeyModifiers,
+    pub fast_multiplier: f64,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let mut keys = HashMap::new();
+        for (a, _, k) in ACTIONS {
+            for k in k.iter() {
+                keys.insert(parse_key(k).unwrap(), *a);
+            }
+        }
+
+        Self {
+            keys,
+            move_step: 1.0,
+            turn_step: 1.0 / 16.0 * TAU,
+            focus_step: 0.125,
+            aperture_step: 0.25,
+            hold_rate: 6.0,
+            fast_modifier: KeyModifiers::SHIFT,
+            fast_multiplier: 4.0,
+        }
+    }
+}
+
+impl Keymap {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut km = Self::default();
+        if !path.exists() { return Ok(km) }
+
+        let mut rebound = Vec::new();
+        for (ln, l) in std::fs::read_to_string(path)?.lines().enumerate() {
+            let l = l.split('#').next().unwrap().trim();
+            let err = |m: &str| format!("{}:{}: {m}", path.display(), ln + 1);
+
+            let Some((k, v)) = l.split_once(char::is_whitespace) else {
+                if l.is_empty() { continue }
+                return Err(err("expected `<name> <value>`").into());
+            };
+            let v = v.trim();
+            let num = || v.parse::<f64>().map_err(|_| err(&format!("`{v}` is not a number")));
+
+            match k {
+                "move_step" => km.move_step = num()?,
+                "turn_step" => km.turn_step = num()?,
+                "focus_step" => km.focus_step = num()?,
+                "aperture_step" => km.aperture_step = num()?,
+                "hold_rate" => km.hold_rate = num()?,
+                "fast_multiplier" => km.fast_multiplier = num()?,
+                "fast_modifier" => km.fast_modifier = match v {
+                    "shift" => KeyModifiers::SHIFT,
+                    "ctrl" => KeyModifiers::CONTROL,
+                    "alt" => KeyModifiers::ALT,
+                    _ => return Err(err(&format!("unknown modifier `{v}`")).into()),
+                },
+                _ => {
+                    let (a, ..) = ACTIONS.iter().find(|a| a.1 == k).ok_or_else(|| err(&format!("unknown action `{k}`")))?;
+
+                    // the first binding of an action in the config replaces its default keys
+                    if !rebound.contains(a) {
+                        km.keys.retain(|_, b| b != a);
+                        rebound.push(*a);
+                    }
+                    for key in v.split_whitespace() {
+                        km.keys.insert(parse_key(key).ok_or_else(|| err(&format!("unknown key `{key}`")))?, *a);
+                    }
+                },
+            }
+        }
+
+        Ok(km)
+    }
+
+    pub fn action(&self, code: KeyCode) -> Option<Action> {
+        let code = match code {
+            KeyCode::Char(c) => KeyCode::Char(c.to_ascii_lowercase()),
+            c => c,
+        };
+        self.keys.get(&code).copied()
+    }
+
+    pub fn keys_for(&self, a: Action) -> Vec<String> {
+        let mut k: Vec<String> = self.keys.iter().filter(|(_, b)| **b == a).map(|(k, _)| key_name(*k)).collect();
+        k.sort();
+        k
+    }
+
+    pub fn step(&self, a: Action) -> f64 {
+        match a {
+            Action::TurnUp | Action::TurnDown | Action::TurnLeft | Action::TurnRight => self.turn_step,
+            Action::FocusFar | Action::FocusNear => self.focus_step,
+            Action::ApertureUp | Action::ApertureDown => self.aperture_step,
+            _ => self.move_step,
+        }
+    }
+}
+
+const NAMED_KEYS: &[(&str, KeyCode)] = &[
+    ("esc", KeyCode::Esc),
+    ("enter", KeyCode::Enter),
+    ("tab", KeyCode::Tab),
+    ("space", KeyCode::Char(' ')),
+    ("backspace", KeyCode::Backspace),
+    ("delete", KeyCode::Delete),
+    ("insert", KeyCode::Insert),
+    ("home", KeyCode::Home),
+    ("end", KeyCode::End),
+    ("pageup", KeyCode::PageUp),
+    ("pagedown", KeyCode::PageDown),
+    ("up", KeyCode::Up),
+    ("down", KeyCode::Down),
+    ("left", KeyCode::Left),
+    ("right", KeyCode::Right),
+];
+
+pub fn parse_key(s: &str) -> Option<KeyCode> {
+    if let Some((_, k)) = NAMED_KEYS.iter().find(|k| k.0 == s) {
+        return Some(*k);
+    }
+    if let Some(n) = s.strip_prefix('f').and_then(|n| n.parse().ok()) {
+        return Some(KeyCode::F(n));
+    }
+
+    let mut c = s.chars();
+    match (c.next(), c.next()) {
+        (Some(c), None) => Some(KeyCode::Char(c.to_ascii_lowercase())),
+        _ => None,
+    }
+}
+
+pub fn key_name(k: KeyCode) -> String {
+    if let Some((n, _)) = NAMED_KEYS.iter().find(|n| n.1 == k) {
+        return n.to_string();
+    }
+    match k {
+        KeyCode::F(n) => format!("f{n}"),
+        KeyCode::Char(c) => c.to_string(),
+        k => format!("{k:?}"),
+    }
+}
@@ -1,140 +1,415 @@
-#![allow(cast_ref_to_mut)]
-
 use std::time::SystemTime;
 
 mod terminal;
 mod renderer;
+mod output;
+mod anim;
+mod bookmarks;
+mod keymap;
+mod scene;
+mod checkpoint;
+mod denoise;
+mod tonemap;
+mod post;
+mod rng;
+mod sampler;
+mod filter;
+mod screenshot;
+mod stats;
+mod bench;
+mod compare;
+#[cfg(test)]
+mod regression;
+#[cfg(test)]
+mod intersection;
 
 use nalgebra::base::*;
 use renderer::*;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let size = terminal::init()?;
-    let mut fps = 0.0;
+    let args: Vec<String> = std::env::args().collect();
+    // built-in scenes or only images, so they don't need or look at the scene
+    match args.get(1).map(|a| a.as_str()) {
+        Some("bench") => return run_bench(&args[2..]),
+        Some("compare") if flag(&args, "--plot").is_none() => return run_compare(&args[2..]),
+        _ => (),
+    }
+
     let mut state = renderer::RendererState::default();
-    let mut img = vec![vec![Vector3::default(); size as usize]; size as usize];
-    
-    let s = load_obj()?;
-    for (o, m) in s.iter() {
-        state.scene.push(Object::new(&**o, m));
+    if let Some(s) = flag(&args, "--seed") {
+        state.seed = s.parse().map_err(|_| format!("`{s}` is not a seed, it has to be a whole number"))?;
+    }
+
+    let scene_path = std::path::PathBuf::from(flag(&args, "--scene").unwrap_or(scene::DEFAULT_SCENE));
+    let entries = if scene_path.exists() { scene::load(&scene_path)? } else { default_scene(state.seed)? };
+    state.scene = scene::build(&entries)?;
+    state.tonemap = tonemap_options(&args)?;
+    if let Some(s) = flag(&args, "--sampler") {
+        state.sampler = sampler::Kind::parse(s)?;
+    }
+    if let Some(n) = flag(&args, "--noise") {
+        state.noise_threshold = Some(n.parse().map_err(|_| format!("`{n}` is not a noise threshold"))?);
+    }
+    if let Some(b) = flag(&args, "--bounces") {
+        state.bounces = b.parse().map_err(|_| format!("`{b}` is not a number of bounces"))?;
     }
-    let s = generate_balls();
-    for (o, m) in s.iter() {
-        state.scene.push(Object::new(&**o, m));
+    if let Some(f) = flag(&args, "--filter") {
+        state.filter = filter::Filter::parse(f)?;
     }
-    let (fo, fm) = generate_floor();
-    state.scene.push(Object::new(&fo, &fm));
 
-    let mut fno = 1;
+    let mut viewer = terminal::ViewerState {
+        bookmarks: bookmarks::Bookmarks::for_scene(&scene_path),
+        scene: entries,
+        scene_path,
+        keymap: keymap::Keymap::load(std::path::Path::new(keymap::KEYMAP_PATH))?,
+        post: post_options(&args)?,
+        screenshot: screenshot::Settings::load(std::path::Path::new(flag(&args, "--screenshot").unwrap_or(screenshot::SCREENSHOT_PATH)))?,
+        ..Default::default()
+    };
+    viewer.bookmarks.load()?;
+
+    if let Some(n) = flag(&args, "--bookmark") {
+        let n: usize = n.parse()?;
+        let p = viewer.bookmarks.poses.get(n).copied().flatten()
+            .ok_or_else(|| format!("no bookmark {n} in {}", viewer.bookmarks.path.display()))?;
+        state.set_pose(p);
+    }
+
+    match args.get(1).map(|a| a.as_str()) {
+        Some("anim") => return run_anim(&mut state, &args[2..]),
+        Some("render") => return run_render(&mut state, &viewer.scene, &args[2..], flag(&args, "--checkpoint")),
+        Some("compare") => return run_convergence(&state, &args[2..]),
+        Some("play") => {
+            let a = anim::Animation::load(std::path::Path::new(args.get(2).map_or(terminal::CAMERA_PATH, |a| a.as_str())))?;
+            a.check(&state)?;
+            viewer.playback = Some((SystemTime::now(), a));
+        },
+        _ => (),
+    }
+
+    let size = terminal::init()?;
+    viewer.size = size as usize;
+    let mut fps = 0.0;
+    let mut img = vec![vec![Pixel::default(); size as usize]; size as usize];
+
+    let mut prog = Progressive::default();
+    // the stats as they were at the start of the frame
+    let mut last = stats::Snapshot::default();
+    let fps_target: f64 = flag(&args, "--fps").map_or(Ok(terminal::TARGET_FPS), |f| f.parse().map_err(|_| format!("`{f}` is not a frame rate")))?;
+    viewer.frame = std::time::Duration::from_secs_f64(1.0 / fps_target.max(0.1));
+    // showing the image and everything else a frame does comes out of the time left for rendering
+    let mut overhead = std::time::Duration::ZERO;
+
+    viewer.checkpoint = flag(&args, "--checkpoint").map(std::path::PathBuf::from);
+    if let Some(p) = viewer.checkpoint.as_ref().filter(|p| p.exists()) {
+        let cp = checkpoint::Checkpoint::load(p)
+            .and_then(|cp| cp.check(p, scene::hash(&viewer.scene, &state.scene)?, size as usize).map(|_| cp));
+        let cp = match cp {
+            Ok(cp) => cp,
+            Err(e) => {
+                terminal::prep_exit()?;
+                return Err(e);
+            },
+        };
+
+        state.set_pose(cp.pose);
+        img = cp.img;
+        prog = Progressive::resume(cp.passes);
+        terminal::push_log(&format!("Resumed {} passes from {}", cp.passes, p.display()));
+    }
     loop {
         let s = SystemTime::now();
 
-        let rr = render(&mut state, size as usize, &mut img, fno);
+        if terminal::update(&mut state, &mut viewer) {
+            img = vec![vec![Pixel::default(); size as usize]; size as usize];
+            prog = Progressive::default();
+            state.stats.reset();
+            last = stats::Snapshot::default();
+        }
 
-        terminal::push_image(rr, &format!("t {fps:.1} r {:.1} fno {fno} focus {} aperture {}", 1000.0 / s.elapsed()?.as_millis() as f64, state.focus, state.aperture))?;
-        if terminal::handle_input(&mut state, s.elapsed()?)? {
-            img = vec![vec![Vector3::default(); size as usize]; size as usize];
-            fno = 0;
+        let r = std::time::Instant::now();
+        let budget = viewer.frame.saturating_sub(overhead);
+        loop {
+            prog.render(&mut state, size as usize, &mut img, budget.saturating_sub(r.elapsed()));
+            if prog.converged || r.elapsed() >= budget { break }
+        }
+        let rendering = r.elapsed();
+
+        let t = std::time::Instant::now();
+        let mut rr = preview(&state, &img);
+        if viewer.aov != Aov::Beauty || viewer.denoise || viewer.post.any() {
+            let buf = match viewer.aov {
+                Aov::Beauty if viewer.denoise => viewer.post.apply(&denoise::denoise(&img, &viewer.denoiser)),
+                Aov::Beauty => viewer.post.apply(&layer(&img, Aov::Beauty)),
+                a => layer(&img, a),
+            };
+            rr = to_rgb8(&visualize(viewer.aov, &buf, &state.tonemap));
+        }
+        state.stats.time(stats::Phase::Post, t.elapsed());
+
+        // the drawing is only known after the panel showed it, so it's a frame late
+        let now = state.stats.snapshot();
+        let panel = viewer.panel(&state, prog.passes * SAMPLES_LVL, &now.since(&last));
+        last = now;
+        let buffer = if viewer.aov == Aov::Beauty { String::new() } else { format!(" [{}]", viewer.aov.name()) };
+        let t = std::time::Instant::now();
+        terminal::push_image(rr, &format!("t {fps:.1} r {:.1} fno {} focus {} aperture {}{}{buffer}{}", 1000.0 / s.elapsed()?.as_millis() as f64, prog.passes, state.focus, state.aperture, viewer.mode(), viewer.notice()), &panel, viewer.hud || viewer.edit.selected.is_some())?;
+        state.stats.time(stats::Phase::Display, t.elapsed());
+        overhead = s.elapsed()?.saturating_sub(rendering);
+        if terminal::handle_input(&mut state, &mut viewer, s.elapsed()?)? {
+            img = vec![vec![Pixel::default(); size as usize]; size as usize];
+            prog = Progressive::default();
+            state.stats.reset();
+            last = stats::Snapshot::default();
         };
 
-        fps = 1000.0 / s.elapsed()?.as_millis() as f64;
+        if std::mem::take(&mut viewer.save_checkpoint) || viewer.quit {
+            if let Some(p) = &viewer.checkpoint {
+                save_checkpoint(p, &state, &viewer.scene, &img, prog.started())?;
+                terminal::push_log(&format!("Saved {} passes to {}", prog.started(), p.display()));
+            }
+        }
+        if viewer.quit {
+            return terminal::exit();
+        }
 
-        fno += 1;
+        fps = 1000.0 / s.elapsed()?.as_millis() as f64;
     }
 }
 
-fn generate_floor() -> (Mesh, Material) {
-    (
-        Mesh { ts: vec![
-            Triangle {
-                vp: [
-                    Vector3::new(-1000.0, FLOOR_HEIGHT,  1000.0),
-                    Vector3::new( 1000.0, FLOOR_HEIGHT,  1000.0),
-                    Vector3::new( 1000.0, FLOOR_HEIGHT, -1000.0),
-                ],
-                vn: None
-            },
-            Triangle {
-                vp: [
-                    Vector3::new(-1000.0, FLOOR_HEIGHT,  1000.0),
-                    Vector3::new( 1000.0, FLOOR_HEIGHT, -1000.0),
-                    Vector3::new(-1000.0, FLOOR_HEIGHT, -1000.0),
-                ],
-                vn: None
-            },
-        ]},
-        Material {
-            color: Vector3::new(0.3, 0.5, 1.0),
-            emit_color: Vector3::default(),
-            shininess: 0.4,
-            rough: 0.75,
+fn flag<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter().position(|a| a == name).and_then(|i| args.get(i + 1)).map(|a| a.as_str())
+}
+
+// flags that are followed by a value
+const VALUE_FLAGS: &[&str] = &["--scene", "--bookmark", "--checkpoint", "--format", "--denoise", "--exposure", "--tonemap", "--white-balance", "--post", "--seed", "--sampler", "--filter", "--noise", "--fps", "--time", "--screenshot", "--bounces", "--stats", "--baseline", "--tolerance", "--plot"];
+
+// the arguments that aren't flags or their values
+fn positional(args: &[String]) -> Vec<&str> {
+    let mut p = Vec::new();
+    let mut it = args.iter();
+    while let Some(a) = it.next() {
+        if VALUE_FLAGS.contains(&a.as_str()) {
+            it.next();
+        } else if !a.starts_with("--") {
+            p.push(a.as_str());
         }
-    )
+    }
+    p
 }
 
-const OBJ_SCALE: f64 = 10.0;
-const OBJ_OFFSET: Vector3<f64> = Vector3::new(0.0, 2.0, -3.0);
-const FLOOR_HEIGHT: f64 = 0.0;
+// termray anim <file> [first frame] [last frame] [out dir] [size] [samples] [--format <ext>]
+fn run_anim(state: &mut RendererState, args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let p = positional(args);
+    if p.is_empty() {
+        return Err("usage: termray anim <file> [first frame] [last frame] [out dir] [size] [samples] [--format <ext>]".into());
+    }
 
-fn load_obj() -> Result<Vec<(Box<dyn ObjectKind>, Material)>, Box<dyn std::error::Error>> {
-    let obj = tobj::load_obj("model.obj", &tobj::GPU_LOAD_OPTIONS)?;
-    let (models, materials) = obj;
+    let a = anim::Animation::load(std::path::Path::new(p[0]))?;
+    let first = p.get(1).map_or(Ok(0), |f| f.parse())?;
+    let last = p.get(2).map_or(Ok((a.duration() * a.fps).ceil() as usize), |f| f.parse())?;
+    let dir = p.get(3).copied().unwrap_or("frames");
+    let size = p.get(4).map_or(Ok(terminal::SCREENSHOT_SIZE), |s| s.parse())?;
+    let samples = p.get(5).map_or(Ok(terminal::SCREENSHOT_SAMPLES), |s| s.parse())?;
 
-    let mut buf: Vec<(Box<dyn ObjectKind>, Material)> = Vec::with_capacity(models.len());
+    let ext = flag(args, "--format").unwrap_or("png");
 
-    for i in models.iter() {
-        let mesh = &i.mesh;
+    anim::render_frames(state, &a, first..=last, &std::path::Path::new(dir).join(format!("frame.{ext}")), size, samples, output_options(args)?)
+}
 
-        let mut ts = Vec::with_capacity(mesh.positions.len() / 3);
+// termray render <out file> [size] [samples] [--time <seconds>] [--checkpoint <file>] [--half] [--png16] [--aovs] [--denoise <strength>] [--stats <file>]
+fn run_render(state: &mut RendererState, entries: &[scene::Entry], args: &[String], cp_path: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    let p = positional(args);
+    if p.is_empty() {
+        return Err("usage: termray render <out file> [size] [samples] [--time <seconds>] [--checkpoint <file>] [--half] [--png16] [--aovs] [--denoise <strength>] [--stats <file>]".into());
+    }
 
-        for j in mesh.indices.chunks(3) {
-            fn load(a: &Vec<f64>, j: &[u32], i: usize) -> Vector3<f64> {
-                Vector3::new(
-                    a[j[i] as usize * 3 + 0],
-                    a[j[i] as usize * 3 + 1],
-                    a[j[i] as usize * 3 + 2],
-                )
-            }
+    let out = std::path::Path::new(p[0]);
+    output::format(out)?;
+    let opts = output_options(args)?;
+    let size = p.get(1).map_or(Ok(terminal::SCREENSHOT_SIZE), |s| s.parse())?;
+    let time = flag(args, "--time").map(|t| t.parse::<f64>().map_err(|_| format!("`{t}` is not a number of seconds"))).transpose()?;
+    // with a time limit the number of passes only counts if it was given too
+    let samples = match p.get(2) {
+        Some(s) => s.parse()?,
+        None if time.is_some() => usize::MAX,
+        None => terminal::SCREENSHOT_SAMPLES,
+    };
+    let cp_path = cp_path.map(std::path::Path::new);
+
+    let mut img = vec![vec![Pixel::default(); size]; size];
+    let mut done = 0;
+    if let Some(p) = cp_path.filter(|p| p.exists()) {
+        let cp = checkpoint::Checkpoint::load(p)?;
+        cp.check(p, scene::hash(entries, &state.scene)?, size)?;
+        state.set_pose(cp.pose);
+        img = cp.img;
+        done = cp.passes;
+        println!("resuming from pass {done}");
+    }
 
-            let p0 = load(&mesh.positions, &j, 0);
-            let p1 = load(&mesh.positions, &j, 1);
-            let p2 = load(&mesh.positions, &j, 2);
+    // checkpointed after every pass, so killing the render loses at most one
+    let start = std::time::Instant::now();
+    let mut prog = Progressive::resume(done);
+    while prog.passes < samples {
+        let budget = time.map_or(std::time::Duration::MAX, |t| std::time::Duration::from_secs_f64(t).saturating_sub(start.elapsed()));
+        if budget.is_zero() {
+            println!("out of time after {:.1}s", start.elapsed().as_secs_f64());
+            break
+        }
 
-            let n0 = load(&mesh.normals, &j, 0);
-            let n1 = load(&mesh.normals, &j, 1);
-            let n2 = load(&mesh.normals, &j, 2);
+        prog.render(state, size, &mut img, budget);
+        if prog.tile != 0 { continue }
 
-            ts.push(Triangle {
-                vp: [p0*OBJ_SCALE+OBJ_OFFSET, p1*OBJ_SCALE+OBJ_OFFSET, p2*OBJ_SCALE+OBJ_OFFSET],
-                vn: Some([n0, n1, n2])
-            })
+        if let Some(p) = cp_path {
+            let t = std::time::Instant::now();
+            save_checkpoint(p, state, entries, &img, prog.passes)?;
+            state.stats.time(stats::Phase::Save, t.elapsed());
         }
+        match samples {
+            usize::MAX => println!("pass {} done", prog.passes),
+            _ => println!("pass {}/{samples} done", prog.passes),
+        }
+        if prog.converged {
+            println!("every pixel is below the noise threshold");
+            break
+        }
+    }
+    // the part of a pass that fit in the time is kept as well
+    let t = std::time::Instant::now();
+    if let (Some(p), true) = (cp_path, prog.tile != 0) {
+        save_checkpoint(p, state, entries, &img, prog.started())?;
+    }
+    output::save(out, &output::layers(&img, opts), opts)?;
+    state.stats.time(stats::Phase::Save, t.elapsed());
+
+    // the output of one render to the next, to catch it getting slower
+    let json = state.stats.snapshot().json(&[("size", size as f64), ("passes", (prog.started() - done) as f64)], start.elapsed());
+    match flag(args, "--stats") {
+        Some(p) => std::fs::write(p, json)?,
+        None => print!("{json}"),
+    }
+    Ok(())
+}
 
-        let mat = match mesh.material_id {
-            Some(i) => {
-                fn to(a: [f64;3]) -> Vector3<f64> {
-                    Vector3::new(a[0], a[1], a[2])
-                }
+// termray bench [out.json] [--baseline <file>] [--tolerance <percent>]
+fn run_bench(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let p = positional(args);
+    let tolerance = match flag(args, "--tolerance") {
+        Some(t) => t.parse::<f64>().map_err(|_| format!("`{t}` is not a percentage"))? / 100.0,
+        None => bench::TOLERANCE,
+    };
+
+    let regressions = bench::run(p.first().map(std::path::Path::new), flag(args, "--baseline").map(std::path::Path::new), tolerance)?;
+    if regressions > 0 {
+        return Err(format!("{regressions} of the scenes got more than {:.0}% slower than the baseline", tolerance * 100.0).into());
+    }
+    Ok(())
+}
 
-                let om = &materials.as_ref().unwrap()[i];
-                let mut nm = Material::default();
+// termray compare <image> <reference> [diff.png]
+fn run_compare(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let p = positional(args);
+    if p.len() < 2 {
+        return Err("usage: termray compare <image> <reference> [diff.png] or termray compare <reference> --plot <plot.svg> [passes]".into());
+    }
+    let diff = p.get(2).map(std::path::Path::new);
+    // checked first so a typo doesn't come up only after the comparison
+    if let Some(d) = diff.filter(|d| output::format(d).ok() != Some("png")) {
+        return Err(format!("the diff image {} has to be a png", d.display()).into());
+    }
 
-                if om.diffuse.is_some() { nm.color = to(om.diffuse.unwrap()); }
-                if om.ambient.is_some() { nm.emit_color = to(om.ambient.unwrap()); }
-                if om.shininess.is_some() { nm.shininess = om.shininess.unwrap(); }
-                if om.dissolve.is_some() { nm.rough = om.dissolve.unwrap(); }
+    let load = |p: &str| output::load(std::path::Path::new(p)).map_err(|e| format!("can't load {p}: {e}"));
+    let (m, err) = compare::compare(&load(p[0])?, &load(p[1])?)?;
+    compare::print(&m);
+    if let Some(d) = diff {
+        compare::save_diff(d, &err)?;
+    }
+    Ok(())
+}
 
-                nm
-            },
-            None => Material::default()
-        };
+// termray compare <reference> --plot <plot.svg> [passes], renders the current scene and pose, which have to be the ones the reference was rendered with
+fn run_convergence(state: &RendererState, args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let p = positional(args);
+    let (Some(reference), Some(plot)) = (p.first(), flag(args, "--plot")) else {
+        return Err("usage: termray compare <reference> --plot <plot.svg> [passes]".into());
+    };
+    let passes = p.get(1).map_or(Ok(compare::PASSES), |s| s.parse())?;
+    let reference = output::load(std::path::Path::new(reference)).map_err(|e| format!("can't load {reference}: {e}"))?;
+
+    let points = compare::convergence(state, &reference, passes)?;
+    compare::plot(std::path::Path::new(plot), &points)
+}
 
-        buf.push((Box::new(Mesh { ts }), mat))
+fn output_options(args: &[String]) -> Result<output::Options, Box<dyn std::error::Error>> {
+    Ok(output::Options {
+        half: args.iter().any(|a| a == "--half"),
+        deep_png: args.iter().any(|a| a == "--png16"),
+        aovs: args.iter().any(|a| a == "--aovs"),
+        tonemap: tonemap_options(args)?,
+        post: post_options(args)?,
+        denoise: match flag(args, "--denoise") {
+            Some(s) => Some(denoise::Settings {
+                strength: s.parse().map_err(|_| format!("`{s}` is not a denoise strength"))?,
+                ..Default::default()
+            }),
+            None => None,
+        },
+    })
+}
+
+fn tonemap_options(args: &[String]) -> Result<tonemap::Tonemap, Box<dyn std::error::Error>> {
+    let num = |f: &str, d: f64| flag(args, f).map_or(Ok(d), |v| v.parse().map_err(|_| format!("`{v}` is not a number for {f}")));
+    Ok(tonemap::Tonemap {
+        exposure: num("--exposure", 0.0)?,
+        operator: flag(args, "--tonemap").map_or(Ok(tonemap::Operator::default()), tonemap::Operator::parse)?,
+        white_balance: num("--white-balance", tonemap::NEUTRAL_KELVIN)?,
+    })
+}
+
+fn post_options(args: &[String]) -> Result<post::Post, Box<dyn std::error::Error>> {
+    post::Post::load(std::path::Path::new(flag(args, "--post").unwrap_or(post::POST_PATH)))
+}
+
+fn save_checkpoint(path: &std::path::Path, state: &RendererState, entries: &[scene::Entry], img: &[Vec<Pixel>], passes: usize) -> Result<(), Box<dyn std::error::Error>> {
+    checkpoint::Checkpoint {
+        scene_hash: scene::hash(entries, &state.scene)?,
+        passes,
+        pose: state.pose(),
+        img: img.to_vec(),
+    }.save(path)
+}
+
+fn default_scene(seed: u64) -> Result<Vec<scene::Entry>, Box<dyn std::error::Error>> {
+    let mut entries = Vec::new();
+
+    let models = scene::load_obj(std::path::Path::new(MODEL), OBJ_SCALE, OBJ_OFFSET)?.len();
+    for model in 0..models {
+        entries.push(scene::Entry::new(scene::Shape::Obj {
+            path: MODEL.to_string(), model, scale: OBJ_SCALE, offset: OBJ_OFFSET
+        }, None));
     }
+    entries.extend(balls_and_floor(seed));
+
+    Ok(entries)
+}
 
-    Ok(buf)
+fn balls_and_floor(seed: u64) -> Vec<scene::Entry> {
+    let mut entries = generate_balls(seed);
+    entries.push(scene::Entry::new(scene::Shape::Floor { height: FLOOR_HEIGHT, size: 1000.0 }, Some(Material {
+        color: Vector3::new(0.3, 0.5, 1.0),
+        emit_color: Vector3::default(),
+        shininess: 0.4,
+        rough: 0.75,
+    })));
+    entries
 }
 
+const MODEL: &str = "model.obj";
+const OBJ_SCALE: f64 = 10.0;
+const OBJ_OFFSET: Vector3<f64> = Vector3::new(0.0, 2.0, -3.0);
+const FLOOR_HEIGHT: f64 = 0.0;
+
 const BALLS_SQRT: i32 = 10;
 // https://coolors.co/palette/f94144-f3722c-f8961e-f9844a-f9c74f-90be6d-43aa8b-4d908e-577590-277da1
 const COLORS: &[(u8, u8, u8)] = &[
@@ -150,11 +425,11 @@ const COLORS: &[(u8, u8, u8)] = &[
     (0x27, 0x7D, 0xA1),
 ];
 
-fn generate_balls() -> Vec<(Box<dyn ObjectKind>, Material)> {
-    let mut buf: Vec<(Box<dyn ObjectKind>, Material)> = Vec::with_capacity(BALLS_SQRT as usize * BALLS_SQRT as usize + 2);
+fn generate_balls(seed: u64) -> Vec<scene::Entry> {
+    let mut buf = Vec::with_capacity(BALLS_SQRT as usize * BALLS_SQRT as usize + 2);
 
     use rand::Rng;
-    let mut rng = rand::thread_rng();
+    let mut rng = rng::Pcg::new(seed, 0);
 
     for x in -BALLS_SQRT/2..BALLS_SQRT/2 {
         for z in -BALLS_SQRT/2..BALLS_SQRT/2 {
@@ -179,13 +454,13 @@ fn generate_balls() -> Vec<(Box<dyn ObjectKind>, Material)> {
             );
             let emit_color = if rng.gen() { color * rng.gen_range(5.0..20.0) } else { Vector3::default() };
 
-            buf.push((Box::new(Sphere {
+            buf.push(scene::Entry::new(scene::Shape::Sphere {
                 c, r: 0.2
-            }), Material {
+            }, Some(Material {
                 color, emit_color,
                 shininess: rng.gen_range(0.0..1.0),
                 rough: rng.gen_range(0.0..1.0),
-            }));
+            })));
         }
     }
 
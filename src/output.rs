@@ -0,0 +1,288 @@
+use nalgebra::base::*;
+use std::path::Path;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use crate::renderer::{Aov, Pixel};
+
+#[derive(Clone, Copy, Default)]
+pub struct Options {
+    // exr channels as 16 bit floats instead of 32 bit ones
+    pub half: bool,
+    // png with 16 bits per channel instead of 8
+    pub deep_png: bool,
+    // every buffer instead of just the beauty pass
+    pub aovs: bool,
+    pub denoise: Option<crate::denoise::Settings>,
+    pub post: crate::post::Post,
+    // only used by png, the float formats stay linear
+    pub tonemap: crate::tonemap::Tonemap,
+}
+
+// the buffers `save` should write for an accumulation buffer, with the beauty pass denoised and post processed if asked to
+pub fn layers(img: &[Vec<Pixel>], opts: Options) -> Vec<(Aov, Vec<Vec<Vector3<f64>>>)> {
+    let mut l = crate::renderer::layers(img, opts.aovs);
+    if let Some(s) = &opts.denoise {
+        l[0].1 = crate::denoise::denoise(img, s);
+    }
+    if opts.post.any() {
+        l[0].1 = opts.post.apply(&l[0].1);
+    }
+    l
+}
+
+pub const FORMATS: &[&str] = &["png", "exr", "pfm", "hdr"];
+
+// writes linear radiance in whichever format the extension asks for. exr keeps every layer in one file,
+// the other formats write the first layer to `path` and the rest next to it as `<name>.<layer>.<ext>`
+pub fn save(path: &Path, layers: &[(Aov, Vec<Vec<Vector3<f64>>>)], opts: Options) -> Result<(), Box<dyn std::error::Error>> {
+    let format = format(path)?;
+    if format == "exr" {
+        return save_exr(path, layers, opts.half);
+    }
+
+    for (i, (aov, img)) in layers.iter().enumerate() {
+        let path = if i == 0 { path.to_path_buf() } else { layer_path(path, aov.name()) };
+        match format {
+            "png" => {
+                let img = crate::renderer::visualize(*aov, img, &opts.tonemap);
+                if opts.deep_png {
+                    save_png16(&path, &img)?
+                } else {
+                    save_png(&path, &crate::renderer::to_rgb8(&img))?
+                }
+            },
+            "pfm" => save_pfm(&path, img)?,
+            _ => save_hdr(&path, img)?,
+        }
+    }
+
+    Ok(())
+}
+
+// `out.png` becomes `out.depth.png`, a `.part` extension stays last
+fn layer_path(path: &Path, layer: &str) -> std::path::PathBuf {
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+    let (stem, ext) = match name.strip_suffix(".part") {
+        Some(n) => (n.rsplit_once('.').map_or(n, |s| s.0), format!("{}.part", n.rsplit_once('.').map_or("", |s| s.1))),
+        None => name.rsplit_once('.').map_or((name, String::new()), |(s, e)| (s, e.to_string())),
+    };
+    path.with_file_name(format!("{stem}.{layer}.{ext}"))
+}
+
+// checked before rendering as well, so a typo doesn't throw away a long render
+pub fn format(path: &Path) -> Result<&'static str, Box<dyn std::error::Error>> {
+    let ext = |p: &Path| p.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+    let mut e = ext(path);
+    // a half written `.part` file is saved in the format it will end up as
+    if e == "part" {
+        e = path.file_stem().map_or(String::new(), |s| ext(Path::new(s)));
+    }
+
+    FORMATS.iter().find(|f| **f == e).copied()
+        .ok_or_else(|| format!("can't save {}, the extension has to be one of {}", path.display(), FORMATS.join(", ")).into())
+}
+
+// linear radiance from a png, exr or pfm file. pngs are taken to be sRGB encoded, like the ones `save` writes
+pub fn load(path: &Path) -> Result<Vec<Vec<Vector3<f64>>>, Box<dyn std::error::Error>> {
+    match path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase().as_str() {
+        "png" => load_png(path),
+        "exr" => {
+            let img = exr::prelude::read_first_rgba_layer_from_file(path,
+                |size, _| vec![vec![Vector3::default(); size.width()]; size.height()],
+                |img: &mut Vec<Vec<Vector3<f64>>>, p, (r, g, b, _): (f32, f32, f32, f32)| {
+                    img[p.y()][p.x()] = Vector3::new(r as f64, g as f64, b as f64)
+                })?;
+            Ok(img.layer_data.channel_data.pixels)
+        },
+        "pfm" => load_pfm(path),
+        _ => Err("the extension has to be one of png, exr, pfm".into()),
+    }
+}
+
+fn load_png(path: &Path) -> Result<Vec<Vec<Vector3<f64>>>, Box<dyn std::error::Error>> {
+    let mut decoder = png::Decoder::new(File::open(path)?);
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info()?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf)?;
+
+    let (w, h) = (info.width as usize, info.height as usize);
+    let channels = info.color_type.samples();
+    let sample = |i: usize| match info.bit_depth {
+        png::BitDepth::Sixteen => u16::from_be_bytes([buf[i * 2], buf[i * 2 + 1]]) as f64 / 65535.0,
+        _ => buf[i] as f64 / 255.0,
+    };
+    Ok((0..h).map(|y| (0..w).map(|x| {
+        let i = (y * w + x) * channels;
+        // grey, with or without alpha, is the same in every channel
+        let v = if channels < 3 { Vector3::repeat(sample(i)) } else { Vector3::new(sample(i), sample(i + 1), sample(i + 2)) };
+        v.map(crate::tonemap::srgb_to_linear)
+    }).collect()).collect())
+}
+
+fn load_pfm(path: &Path) -> Result<Vec<Vec<Vector3<f64>>>, Box<dyn std::error::Error>> {
+    let data = std::fs::read(path)?;
+    let err = || format!("{} isn't a pfm file", path.display());
+
+    // the type, width, height and scale, then a single whitespace character before the data
+    let mut words = Vec::new();
+    let mut i = 0;
+    while words.len() < 4 {
+        while data.get(i).is_some_and(|c| c.is_ascii_whitespace()) { i += 1 }
+        let start = i;
+        while data.get(i).is_some_and(|c| !c.is_ascii_whitespace()) { i += 1 }
+        if start == i { return Err(err().into()) }
+        words.push(std::str::from_utf8(&data[start..i]).map_err(|_| err())?);
+    }
+    let channels = match words[0] {
+        "PF" => 3,
+        "Pf" => 1,
+        _ => return Err(err().into()),
+    };
+    let w: usize = words[1].parse().map_err(|_| err())?;
+    let h: usize = words[2].parse().map_err(|_| err())?;
+    let scale: f64 = words[3].parse().map_err(|_| err())?;
+
+    let data = data.get(i + 1..).ok_or_else(err)?;
+    let size = w.checked_mul(h).and_then(|n| n.checked_mul(channels * 4)).ok_or_else(err)?;
+    if data.len() < size { return Err(format!("{} is cut short", path.display()).into()) }
+    let sample = |i: usize| {
+        let b = [data[i * 4], data[i * 4 + 1], data[i * 4 + 2], data[i * 4 + 3]];
+        (if scale < 0.0 { f32::from_le_bytes(b) } else { f32::from_be_bytes(b) }) as f64
+    };
+    // rows go from the bottom up
+    Ok((0..h).rev().map(|y| (0..w).map(|x| {
+        let i = (y * w + x) * channels;
+        if channels == 1 { Vector3::repeat(sample(i)) } else { Vector3::new(sample(i), sample(i + 1), sample(i + 2)) }
+    }).collect()).collect())
+}
+
+pub fn save_png(path: &Path, img: &[Vec<(u8, u8, u8)>]) -> Result<(), Box<dyn std::error::Error>> {
+    let h = img.len();
+    let w = img.first().map_or(0, |r| r.len());
+
+    let file = File::create(path)?;
+    let bw = &mut BufWriter::new(file);
+
+    let mut encoder = png::Encoder::new(bw, w as u32, h as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header()?;
+    let mut buf = Vec::with_capacity(w * h * 3);
+
+    for y in img.iter() {
+        for x in y.iter() {
+            buf.push(x.0);
+            buf.push(x.1);
+            buf.push(x.2);
+        }
+    }
+    writer.write_image_data(&buf)?;
+
+    Ok(())
+}
+
+// takes display colors in 0..1, as they come out of `visualize`
+fn save_png16(path: &Path, img: &[Vec<Vector3<f64>>]) -> Result<(), Box<dyn std::error::Error>> {
+    let h = img.len();
+    let w = img.first().map_or(0, |r| r.len());
+
+    let bw = &mut BufWriter::new(File::create(path)?);
+
+    let mut encoder = png::Encoder::new(bw, w as u32, h as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Sixteen);
+
+    let mut writer = encoder.write_header()?;
+    let mut buf = Vec::with_capacity(w * h * 6);
+
+    for v in img.iter().flatten().flat_map(|v| v.iter()) {
+        let v = (v * 65535.0 + 0.5) as u16;
+        buf.extend_from_slice(&v.to_be_bytes());
+    }
+    writer.write_image_data(&buf)?;
+
+    Ok(())
+}
+
+// layers after the first are stored as `<name>.R` etc. in the same part, which is how most tools expect them,
+// depth goes into the usual `Z` channel
+fn save_exr(path: &Path, layers: &[(Aov, Vec<Vec<Vector3<f64>>>)], half: bool) -> Result<(), Box<dyn std::error::Error>> {
+    use exr::prelude::*;
+
+    let first = &layers.first().ok_or("nothing to save")?.1;
+    let h = first.len();
+    let w = first.first().map_or(0, |r| r.len());
+
+    let mut channels = Vec::new();
+    for (i, (aov, img)) in layers.iter().enumerate() {
+        let names: &[&str] = match aov {
+            Aov::ObjectId | Aov::MaterialId => &["id"],
+            _ if aov.channels() == 1 => &["Y"],
+            _ => &["R", "G", "B"],
+        };
+        for (c, cn) in names.iter().enumerate() {
+            let v = img.iter().flatten().map(|p| p[c] as f32);
+            // ids and sample counts have to stay exact, which halfs only manage up to 2048
+            let samples = if half && aov.channels() == 3 {
+                FlatSamples::F16(v.map(f16::from_f32).collect())
+            } else {
+                FlatSamples::F32(v.collect())
+            };
+            let name = match aov {
+                _ if i == 0 => cn.to_string(),
+                Aov::Depth => "Z".to_string(),
+                _ => format!("{}.{cn}", aov.name()),
+            };
+            channels.push(AnyChannel::new(name.as_str(), samples));
+        }
+    }
+
+    let layer = Layer::new((w, h), LayerAttributes::default(), Encoding::FAST_LOSSLESS, AnyChannels::sort(channels.into()));
+    Image::from_layer(layer).write().to_file(path)?;
+
+    Ok(())
+}
+
+fn save_pfm(path: &Path, img: &[Vec<Vector3<f64>>]) -> Result<(), Box<dyn std::error::Error>> {
+    let h = img.len();
+    let w = img.first().map_or(0, |r| r.len());
+
+    let mut bw = BufWriter::new(File::create(path)?);
+    // a negative scale means little endian, and rows go from the bottom up
+    write!(bw, "PF\n{w} {h}\n-1.0\n")?;
+    for row in img.iter().rev() {
+        for v in row.iter().flat_map(|v| v.iter()) {
+            bw.write_all(&(*v as f32).to_le_bytes())?;
+        }
+    }
+    bw.flush()?;
+
+    Ok(())
+}
+
+fn save_hdr(path: &Path, img: &[Vec<Vector3<f64>>]) -> Result<(), Box<dyn std::error::Error>> {
+    let h = img.len();
+    let w = img.first().map_or(0, |r| r.len());
+
+    let mut bw = BufWriter::new(File::create(path)?);
+    write!(bw, "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {h} +X {w}\n")?;
+    for v in img.iter().flatten() {
+        bw.write_all(&rgbe(v))?;
+    }
+    bw.flush()?;
+
+    Ok(())
+}
+
+// shared exponent encoding, written flat since readers have to accept scanlines without run lengths
+fn rgbe(v: &Vector3<f64>) -> [u8; 4] {
+    let m = v.max();
+    if m < 1e-32 { return [0; 4] }
+
+    let e = m.log2().floor() as i32 + 1;
+    let s = 256.0 / 2f64.powi(e);
+    [(v[0].max(0.0) * s) as u8, (v[1].max(0.0) * s) as u8, (v[2].max(0.0) * s) as u8, (e + 128) as u8]
+}
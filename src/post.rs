@@ -0,0 +1,183 @@
+use nalgebra::base::*;
+use rayon::prelude::*;
+use std::path::Path;
+
+pub const POST_PATH: &str = "post.cfg";
+
+// sizes are relative to the image width, so the preview and a bigger export look the same
+#[derive(Clone, Copy, Debug)]
+pub struct Post {
+    pub bloom: bool,
+    // only light brighter than this blooms
+    pub bloom_threshold: f64,
+    pub bloom_strength: f64,
+    pub bloom_radius: f64,
+
+    pub vignette: bool,
+    // how much darker the corners get
+    pub vignette_strength: f64,
+
+    pub chromatic: bool,
+    // how far red and blue are pulled apart in the corners
+    pub chromatic_amount: f64,
+
+    pub grain: bool,
+    pub grain_amount: f64,
+}
+
+impl Default for Post {
+    fn default() -> Self {
+        Self {
+            bloom: false,
+            bloom_threshold: 1.0,
+            bloom_strength: 0.3,
+            bloom_radius: 0.02,
+            vignette: false,
+            vignette_strength: 0.4,
+            chromatic: false,
+            chromatic_amount: 0.005,
+            grain: false,
+            grain_amount: 0.05,
+        }
+    }
+}
+
+impl Post {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut p = Self::default();
+        if !path.exists() { return Ok(p) }
+
+        for (ln, l) in std::fs::read_to_string(path)?.lines().enumerate() {
+            let l = l.split('#').next().unwrap().trim();
+            let err = |m: &str| format!("{}:{}: {m}", path.display(), ln + 1);
+
+            let Some((k, v)) = l.split_once(char::is_whitespace) else {
+                if l.is_empty() { continue }
+                return Err(err("expected `<name> <value>`").into());
+            };
+            let v = v.trim();
+            let num = || v.parse::<f64>().map_err(|_| err(&format!("`{v}` is not a number")));
+            let on = || match v {
+                "on" => Ok(true),
+                "off" => Ok(false),
+                _ => Err(err(&format!("expected `on` or `off`, not `{v}`"))),
+            };
+
+            match k {
+                "bloom" => p.bloom = on()?,
+                "bloom_threshold" => p.bloom_threshold = num()?,
+                "bloom_strength" => p.bloom_strength = num()?,
+                "bloom_radius" => p.bloom_radius = num()?,
+                "vignette" => p.vignette = on()?,
+                "vignette_strength" => p.vignette_strength = num()?,
+                "chromatic_aberration" => p.chromatic = on()?,
+                "chromatic_aberration_amount" => p.chromatic_amount = num()?,
+                "grain" => p.grain = on()?,
+                "grain_amount" => p.grain_amount = num()?,
+                _ => return Err(err(&format!("unknown setting `{k}`")).into()),
+            }
+        }
+
+        Ok(p)
+    }
+
+    pub fn any(&self) -> bool {
+        self.bloom || self.vignette || self.chromatic || self.grain
+    }
+
+    // runs on linear radiance, before tone mapping
+    pub fn apply(&self, img: &[Vec<Vector3<f64>>]) -> Vec<Vec<Vector3<f64>>> {
+        let mut img = img.to_vec();
+        if self.chromatic { img = chromatic(&img, self.chromatic_amount) }
+        if self.bloom { img = bloom(&img, self.bloom_threshold, self.bloom_strength, self.bloom_radius) }
+        if self.vignette { vignette(&mut img, self.vignette_strength) }
+        if self.grain { grain(&mut img, self.grain_amount) }
+        img
+    }
+}
+
+fn size(img: &[Vec<Vector3<f64>>]) -> (usize, usize) {
+    (img.first().map_or(0, |r| r.len()), img.len())
+}
+
+// position relative to the middle, -1..1 across the width
+fn centered(x: usize, y: usize, w: usize, h: usize) -> (f64, f64) {
+    let s = w.max(1) as f64 / 2.0;
+    ((x as f64 + 0.5 - w as f64 / 2.0) / s, (y as f64 + 0.5 - h as f64 / 2.0) / s)
+}
+
+fn bilinear(img: &[Vec<Vector3<f64>>], x: f64, y: f64) -> Vector3<f64> {
+    let (w, h) = size(img);
+    let (x, y) = ((x - 0.5).clamp(0.0, w as f64 - 1.0), (y - 0.5).clamp(0.0, h as f64 - 1.0));
+    let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+    let (x1, y1) = ((x0 + 1).min(w - 1), (y0 + 1).min(h - 1));
+    let (fx, fy) = (x - x0 as f64, y - y0 as f64);
+
+    let top = img[y0][x0].lerp(&img[y0][x1], fx);
+    let bottom = img[y1][x0].lerp(&img[y1][x1], fx);
+    top.lerp(&bottom, fy)
+}
+
+fn chromatic(img: &[Vec<Vector3<f64>>], amount: f64) -> Vec<Vec<Vector3<f64>>> {
+    let (w, h) = size(img);
+    let (cx, cy) = (w as f64 / 2.0, h as f64 / 2.0);
+
+    (0..h).into_par_iter().map(|y| (0..w).map(|x| {
+        // red is magnified and blue shrunk around the middle, green stays where it is
+        let at = |scale: f64| bilinear(img, cx + (x as f64 + 0.5 - cx) * scale, cy + (y as f64 + 0.5 - cy) * scale);
+        Vector3::new(at(1.0 + amount)[0], img[y][x][1], at(1.0 - amount)[2])
+    }).collect()).collect()
+}
+
+fn bloom(img: &[Vec<Vector3<f64>>], threshold: f64, strength: f64, radius: f64) -> Vec<Vec<Vector3<f64>>> {
+    let (w, h) = size(img);
+    let sigma = (radius * w as f64).max(0.5);
+    let r = (sigma * 3.0).ceil() as isize;
+    let kernel: Vec<f64> = (-r..=r).map(|i| (-(i * i) as f64 / (2.0 * sigma * sigma)).exp()).collect();
+    let total: f64 = kernel.iter().sum();
+
+    let bright: Vec<Vec<Vector3<f64>>> = img.iter().map(|row| row.iter().map(|v| v.map(|c| (c - threshold).max(0.0))).collect()).collect();
+
+    // separable gaussian, rows first and then columns
+    let blur = |src: &Vec<Vec<Vector3<f64>>>, horizontal: bool| -> Vec<Vec<Vector3<f64>>> {
+        (0..h).into_par_iter().map(|y| (0..w).map(|x| {
+            let mut s = Vector3::default();
+            for (k, i) in kernel.iter().zip(-r..=r) {
+                let v = if horizontal {
+                    src[y][(x as isize + i).clamp(0, w as isize - 1) as usize]
+                } else {
+                    src[(y as isize + i).clamp(0, h as isize - 1) as usize][x]
+                };
+                s += v * *k;
+            }
+            s / total
+        }).collect()).collect()
+    };
+    let glow = blur(&blur(&bright, true), false);
+
+    img.iter().zip(glow).map(|(row, g)| row.iter().zip(g).map(|(v, g)| v + g * strength).collect()).collect()
+}
+
+fn vignette(img: &mut [Vec<Vector3<f64>>], strength: f64) {
+    let (w, h) = size(img);
+    for (y, row) in img.iter_mut().enumerate() {
+        for (x, v) in row.iter_mut().enumerate() {
+            let (dx, dy) = centered(x, y, w, h);
+            // 1 in the corners of a square image
+            let r2 = (dx * dx + dy * dy) / 2.0;
+            *v *= (1.0 - strength * r2).max(0.0);
+        }
+    }
+}
+
+fn grain(img: &mut [Vec<Vector3<f64>>], amount: f64) {
+    for (y, row) in img.iter_mut().enumerate() {
+        for (x, v) in row.iter_mut().enumerate() {
+            // the same grain every frame, so it doesn't flicker while the image converges
+            let h = ((y as u64) << 32 | x as u64).wrapping_mul(0x9E3779B97F4A7C15);
+            let h = (h ^ (h >> 29)).wrapping_mul(0xBF58476D1CE4E5B9);
+            let n = (h >> 11) as f64 / (1u64 << 53) as f64 * 2.0 - 1.0;
+            *v *= 1.0 + amount * n;
+        }
+    }
+}
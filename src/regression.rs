@@ -0,0 +1,151 @@
+// small seeded scenes rendered the same way every time and compared with the images in `tests/references`.
+// `TERMRAY_BLESS=1 cargo test` writes the references again after a change that's meant to alter the images
+use nalgebra::base::*;
+use std::path::{Path, PathBuf};
+
+use crate::renderer::{self, Aov, Material, Object, Pose, RendererState};
+
+const SIZE: usize = 32;
+const PASSES: usize = 2;
+const SEED: u64 = 1;
+// how far an image may drift, for other platforms' floating point more than anything
+const MIN_PSNR: f64 = 40.0;
+// how much brighter the differences get in the diff image
+const DIFF_SCALE: f64 = 8.0;
+
+const LIGHT: &str = "sphere -2 4 -1 0.5 color 1 1 1 emit 10 9 7\n";
+const FLOOR: &str = "floor 0 100 color 0.8 0.8 0.8 shininess 0.1 rough 0.9\n";
+
+fn pose(x: f64, y: f64, z: f64) -> Pose {
+    Pose { cam_pos: Vector3::new(x, y, z), rot: Vector2::new(0.2, 0.0), ..Default::default() }
+}
+
+fn state(scene: &str, pose: Pose) -> RendererState {
+    let entries = crate::scene::parse(&format!("{LIGHT}{FLOOR}{scene}")).unwrap();
+    let mut rs = RendererState { scene: crate::scene::build(&entries).unwrap(), seed: SEED, ..Default::default() };
+    rs.set_pose(pose);
+    rs
+}
+
+fn reference_path(name: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/references");
+    ["exr", "png"].iter().map(|e| dir.join(format!("{name}.{e}"))).find(|p| p.exists())
+        .unwrap_or_else(|| dir.join(format!("{name}.exr")))
+}
+
+// as the viewer would show it, in 0..1
+fn display(img: &[Vec<Vector3<f64>>]) -> Vec<Vec<Vector3<f64>>> {
+    renderer::visualize(Aov::Beauty, img, &crate::tonemap::Tonemap::default())
+}
+
+fn rmse(a: &[Vec<Vector3<f64>>], b: &[Vec<Vector3<f64>>]) -> f64 {
+    let n = a.iter().flatten().count() * 3;
+    let sum: f64 = a.iter().flatten().zip(b.iter().flatten()).map(|(a, b)| (a - b).norm_squared()).sum();
+    (sum / n.max(1) as f64).sqrt()
+}
+
+fn psnr(rmse: f64) -> f64 {
+    -20.0 * rmse.max(1e-12).log10()
+}
+
+fn check(name: &str, mut rs: RendererState) {
+    let img = renderer::layer(&renderer::render_passes(&mut rs, SIZE, PASSES), Aov::Beauty);
+    let path = reference_path(name);
+
+    if std::env::var_os("TERMRAY_BLESS").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        crate::output::save(&path, &[(Aov::Beauty, img)], Default::default()).unwrap();
+        return;
+    }
+
+    let reference = crate::output::load(&path).map(|r| display(&r)).unwrap_or_else(|e| {
+        panic!("can't load {}: {e}, `TERMRAY_BLESS=1 cargo test` renders the references", path.display())
+    });
+    let img = display(&img);
+    let (w, h) = (reference.first().map_or(0, |r| r.len()), reference.len());
+    assert_eq!((w, h), (SIZE, SIZE), "{} is {w}x{h} instead of {SIZE}x{SIZE}", path.display());
+
+    let e = rmse(&img, &reference);
+    if psnr(e) >= MIN_PSNR { return }
+
+    // the render, the reference and how far apart they are, next to each other
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("target/regression");
+    std::fs::create_dir_all(&dir).unwrap();
+    let diff: Vec<Vec<Vector3<f64>>> = img.iter().zip(&reference)
+        .map(|(a, b)| a.iter().zip(b).map(|(a, b)| ((a - b).abs() * DIFF_SCALE).map(|c| c.min(1.0))).collect()).collect();
+    for (suffix, i) in [("", &img), (".reference", &reference), (".diff", &diff)] {
+        crate::output::save_png(&dir.join(format!("{name}{suffix}.png")), &renderer::to_rgb8(i)).unwrap();
+    }
+    panic!("{name} differs from {}: RMSE {e:.5}, PSNR {:.1} dB (at least {MIN_PSNR} expected), see {}/{name}.*.png",
+        path.display(), psnr(e), dir.display());
+}
+
+#[test]
+fn spheres() {
+    check("spheres", state("\
+sphere 0 1 2 1 color 0.9 0.2 0.2 shininess 0.2 rough 0.8
+sphere 1.6 0.5 1.5 0.5 color 0.2 0.9 0.2 shininess 0.2 rough 0.8
+sphere -1.4 0.3 1 0.3 color 0.2 0.2 0.9 shininess 0.2 rough 0.8
+", pose(0.0, 1.3, -0.5)));
+}
+
+// the blue one faces away from the camera, so it shouldn't show up
+#[test]
+fn triangles() {
+    check("triangles", state("\
+triangle -1.5 0 2 0 2.5 2 1.5 0 2 color 0.9 0.7 0.2 shininess 0 rough 1
+triangle -1.2 0.5 0.5 -0.8 1.3 0.5 -0.4 0.5 0.5 color 0.9 0.2 0.2 shininess 0 rough 1 rot 0 0.5 0
+triangle 1.5 0 1 0 2.5 1 -1.5 0 1 color 0.2 0.5 0.9 shininess 0 rough 1
+", pose(0.0, 1.3, -1.0)));
+}
+
+#[test]
+fn mesh() {
+    const CUBE: &str = "\
+v -1 -1 -1\nv 1 -1 -1\nv 1 1 -1\nv -1 1 -1\nv -1 -1 1\nv 1 -1 1\nv 1 1 1\nv -1 1 1
+vn 0 0 -1\nvn 0 0 1\nvn -1 0 0\nvn 1 0 0\nvn 0 -1 0\nvn 0 1 0
+f 1//1 4//1 3//1 2//1\nf 5//2 6//2 7//2 8//2\nf 1//3 5//3 8//3 4//3
+f 2//4 3//4 7//4 6//4\nf 1//5 2//5 6//5 5//5\nf 4//6 8//6 7//6 3//6
+";
+    let mut rs = state("", pose(0.0, 1.3, -0.5));
+    for (m, _) in crate::scene::parse_obj(CUBE, 0.6, Vector3::new(0.0, 0.6, 1.5)).unwrap() {
+        let mut o = Object::new(Box::new(m), Material { color: Vector3::new(0.3, 0.8, 0.5), ..Default::default() });
+        o.set_transform(renderer::Transform { rot: Vector3::new(0.0, 0.6, 0.0), ..Default::default() });
+        rs.scene.push(o);
+    }
+    check("mesh", rs);
+}
+
+#[test]
+fn materials() {
+    check("materials", state("\
+sphere -1.5 0.5 1.5 0.5 color 0.9 0.9 0.9 shininess 0.95 rough 0.02
+sphere -0.5 0.5 1.5 0.5 color 0.9 0.6 0.2 shininess 0.7 rough 0.4
+sphere 0.5 0.5 1.5 0.5 color 0.2 0.4 0.9 shininess 0 rough 1
+sphere 1.5 0.5 1.5 0.5 color 1 0.3 0.1 emit 3 1 0.3
+sphere 0 0.3 0.5 0.3 color 0.5 0.5 0.5 no_shadow
+sphere 1 1.5 2.5 0.3 color 1 1 1 emit 20 20 20 no_light
+", pose(0.0, 1.0, -0.5)));
+}
+
+#[test]
+fn camera() {
+    let scene = "\
+sphere -1 0.5 0.5 0.5 color 0.9 0.2 0.2 shininess 0.2 rough 0.8
+sphere 0 0.5 2 0.5 color 0.2 0.9 0.2 shininess 0.2 rough 0.8
+sphere 1 0.5 4 0.5 color 0.2 0.2 0.9 shininess 0.2 rough 0.8
+";
+    // depth of field focused on the middle sphere
+    check("camera_focus", state(scene, Pose { focus: 2.5, aperture: 4.0, ..pose(0.0, 1.0, -0.5) }));
+    // turned to the side and tilted down
+    check("camera_turned", state(scene, Pose { rot: Vector2::new(0.4, 0.3), ..pose(1.5, 2.0, -0.5) }));
+}
+
+#[test]
+fn sampling() {
+    let mut rs = state("sphere 0 1 2 1 color 0.9 0.9 0.9 shininess 0.5 rough 0.3\n", pose(0.0, 1.3, -0.5));
+    rs.sampler = crate::sampler::Kind::Halton;
+    rs.filter = crate::filter::Filter::Mitchell;
+    rs.bounces = 3;
+    check("sampling", rs);
+}
@@ -1,36 +1,340 @@
 use nalgebra::base::*;
+use nalgebra::geometry::Rotation3;
 use rayon::prelude::*;
-use rand::{Rng, rngs::ThreadRng};
+use crate::sampler::{self, Sampler};
+use crate::stats::{self, Counter, Phase, Stats};
+use std::time::{Duration, Instant};
+use std::sync::atomic::{AtomicBool, Ordering};
 
 pub const LIGHT_BOUNCES : usize = 16;
 pub const SAMPLES_LVL   : usize = 16;
-pub const RNG_LIMIT     : usize = 256;
+// width and height of the pieces a pass is split into
+pub const TILE_SIZE: usize = 16;
+// a pixel's noise estimate isn't trusted before it has this many samples
+pub const MIN_ADAPTIVE_SAMPLES: usize = 4 * SAMPLES_LVL;
 
 pub const SKY_LIGHT: Vector3<f64> = Vector3::new(1.0, 1.0, 0.8);
 //pub const SKY_LIGHT: Vector3<f64> = Vector3::new(0.0, 0.0, 0.0);
 
-#[derive(Default)]
-pub struct RendererState<'a> {
+pub struct RendererState {
     pub cam_pos: Vector3<f64>,
     pub rot: Vector2<f64>,
-    pub scene: Vec<Object<'a>>,
+    pub scene: Vec<Object>,
+    pub sky_light: Vector3<f64>,
 
     pub focus: f64,
-    pub aperture: f64
+    pub aperture: f64,
+
+    pub tonemap: crate::tonemap::Tonemap,
+
+    // the same seed renders the same image, however the work gets split between threads
+    pub seed: u64,
+    pub bounces: usize,
+    pub sampler: sampler::Kind,
+    pub filter: crate::filter::Filter,
+    // pixels whose error drops below this stop getting samples
+    pub noise_threshold: Option<f64>,
+    // pixels that got samples in the last pass
+    pub active_pixels: usize,
+
+    pub stats: Stats,
+    // tiles that haven't started yet are skipped while this is set
+    pub cancel: AtomicBool,
 }
 
-pub struct Object<'a> {
-    pub kind: &'a dyn ObjectKind,
-    pub material: &'a Material
+impl Default for RendererState {
+    fn default() -> Self {
+        Self {
+            cam_pos: Vector3::default(),
+            rot: Vector2::default(),
+            scene: Vec::new(),
+            sky_light: SKY_LIGHT,
+            focus: 0.0,
+            aperture: 0.0,
+            tonemap: crate::tonemap::Tonemap::default(),
+            seed: 0,
+            bounces: LIGHT_BOUNCES,
+            sampler: sampler::Kind::default(),
+            filter: crate::filter::Filter::default(),
+            noise_threshold: None,
+            active_pixels: 0,
+            stats: Stats::default(),
+            cancel: AtomicBool::new(false),
+        }
+    }
 }
 
-impl<'a> Object<'a> {
-    pub fn new(k: &'a dyn ObjectKind, m: &'a Material) -> Self {
-        Self { kind: k, material: m }
+impl RendererState {
+    pub fn pose(&self) -> Pose {
+        Pose { cam_pos: self.cam_pos, rot: self.rot, focus: self.focus, aperture: self.aperture }
+    }
+
+    // the same camera and settings for another copy of the scene
+    pub fn with_scene(&self, scene: Vec<Object>) -> Self {
+        Self {
+            cam_pos: self.cam_pos,
+            rot: self.rot,
+            scene,
+            sky_light: self.sky_light,
+            focus: self.focus,
+            aperture: self.aperture,
+            tonemap: self.tonemap,
+            seed: self.seed,
+            bounces: self.bounces,
+            sampler: self.sampler,
+            filter: self.filter,
+            noise_threshold: self.noise_threshold,
+            ..Default::default()
+        }
+    }
+
+    pub fn set_pose(&mut self, p: Pose) {
+        self.cam_pos = p.cam_pos;
+        self.rot = p.rot;
+        self.focus = p.focus;
+        self.aperture = p.aperture;
     }
 }
 
-#[derive(Clone)]
+// the buffers a render produces next to the beauty pass
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Aov {
+    #[default]
+    Beauty,
+    Depth,
+    Normal,
+    Albedo,
+    ObjectId,
+    MaterialId,
+    Direct,
+    Indirect,
+    Emission,
+    // how many samples landed in each pixel
+    Samples,
+}
+
+pub const AOVS: &[(Aov, &str)] = &[
+    (Aov::Beauty,     "beauty"),
+    (Aov::Depth,      "depth"),
+    (Aov::Normal,     "normal"),
+    (Aov::Albedo,     "albedo"),
+    (Aov::ObjectId,   "object_id"),
+    (Aov::MaterialId, "material_id"),
+    (Aov::Direct,     "direct"),
+    (Aov::Indirect,   "indirect"),
+    (Aov::Emission,   "emission"),
+    (Aov::Samples,    "samples"),
+];
+
+impl Aov {
+    pub fn name(&self) -> &'static str {
+        AOVS.iter().find(|a| a.0 == *self).unwrap().1
+    }
+
+    // how many of the three components mean anything
+    pub fn channels(&self) -> usize {
+        match self {
+            Aov::Depth | Aov::ObjectId | Aov::MaterialId | Aov::Samples => 1,
+            _ => 3,
+        }
+    }
+
+    // buffers holding light, which get tone mapped like the image itself
+    pub fn radiance(&self) -> bool {
+        matches!(self, Aov::Beauty | Aov::Direct | Aov::Indirect | Aov::Emission)
+    }
+}
+
+// what is accumulated for one pixel: sums of every sample times its filter weight, except for the ids which come
+// from the latest sample that hit something in the pixel and are -1 where nothing was hit
+#[derive(Clone, Copy)]
+pub struct Pixel {
+    pub color: Vector3<f64>,
+    pub depth: f64,
+    pub normal: Vector3<f64>,
+    pub albedo: Vector3<f64>,
+    pub direct: Vector3<f64>,
+    pub indirect: Vector3<f64>,
+    pub emission: Vector3<f64>,
+    pub object: f64,
+    pub material: f64,
+    pub weight: f64,
+
+    // luminance of the samples that landed in this pixel, for its noise estimate
+    pub samples: f64,
+    pub lum: f64,
+    pub lum2: f64,
+}
+
+impl Default for Pixel {
+    fn default() -> Self {
+        Self {
+            color: Vector3::default(),
+            depth: 0.0,
+            normal: Vector3::default(),
+            albedo: Vector3::default(),
+            direct: Vector3::default(),
+            indirect: Vector3::default(),
+            emission: Vector3::default(),
+            object: -1.0,
+            material: -1.0,
+            weight: 0.0,
+            samples: 0.0,
+            lum: 0.0,
+            lum2: 0.0,
+        }
+    }
+}
+
+impl Pixel {
+    pub fn add(&mut self, o: &Pixel) {
+        self.color += o.color;
+        self.depth += o.depth;
+        self.normal += o.normal;
+        self.albedo += o.albedo;
+        self.direct += o.direct;
+        self.indirect += o.indirect;
+        self.emission += o.emission;
+        self.weight += o.weight;
+        self.samples += o.samples;
+        self.lum += o.lum;
+        self.lum2 += o.lum2;
+        if o.object >= 0.0 {
+            self.object = o.object;
+            self.material = o.material;
+        }
+    }
+
+    // standard error of the mean luminance, relative to its square root so dark pixels aren't held to a much
+    // stricter standard than bright ones
+    pub fn error(&self) -> f64 {
+        if self.samples < MIN_ADAPTIVE_SAMPLES as f64 { return f64::INFINITY }
+
+        let mean = self.lum / self.samples;
+        let var = (self.lum2 / self.samples - mean * mean).max(0.0) / (self.samples - 1.0);
+        var.sqrt() / mean.sqrt().max(1e-3)
+    }
+
+    // adds one sample, whose own weight is ignored
+    pub fn splat(&mut self, s: &Pixel, w: f64) {
+        self.color += s.color * w;
+        self.depth += s.depth * w;
+        self.normal += s.normal * w;
+        self.albedo += s.albedo * w;
+        self.direct += s.direct * w;
+        self.indirect += s.indirect * w;
+        self.emission += s.emission * w;
+        self.weight += w;
+    }
+
+    pub fn get(&self, aov: Aov) -> Vector3<f64> {
+        // filters with negative lobes can get close to 0 at the first samples
+        let n = if self.weight.abs() < 1e-9 { f64::INFINITY } else { self.weight };
+        match aov {
+            Aov::Beauty => self.color / n,
+            Aov::Depth => Vector3::repeat(self.depth / n),
+            Aov::Normal => self.normal / n,
+            Aov::Albedo => self.albedo / n,
+            Aov::ObjectId => Vector3::repeat(self.object),
+            Aov::MaterialId => Vector3::repeat(self.material),
+            Aov::Direct => self.direct / n,
+            Aov::Indirect => self.indirect / n,
+            Aov::Emission => self.emission / n,
+            Aov::Samples => Vector3::repeat(self.samples),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Pose {
+    pub cam_pos: Vector3<f64>,
+    pub rot: Vector2<f64>,
+    pub focus: f64,
+    pub aperture: f64,
+}
+
+pub struct Object {
+    pub kind: Box<dyn ObjectKind>,
+    pub material: Material,
+    pub visible: bool,
+    pub casts_shadow: bool,
+    pub emits_light: bool,
+    transform: Transform,
+    to_world: Option<(Matrix4<f64>, Matrix4<f64>)>,
+}
+
+impl Object {
+    pub fn new(k: Box<dyn ObjectKind>, m: Material) -> Self {
+        Self {
+            kind: k, material: m,
+            visible: true, casts_shadow: true, emits_light: true,
+            transform: Transform::default(), to_world: None
+        }
+    }
+
+    pub fn emission(&self) -> Vector3<f64> {
+        if self.emits_light { self.material.emit_color } else { Vector3::default() }
+    }
+
+    pub fn transform(&self) -> &Transform {
+        &self.transform
+    }
+
+    pub fn set_transform(&mut self, t: Transform) {
+        self.transform = t;
+        self.to_world = if t == Transform::default() {
+            None
+        } else {
+            let m = t.matrix(self.kind.center());
+            Some((m, m.try_inverse().unwrap_or_else(Matrix4::identity)))
+        };
+    }
+
+    pub fn try_ray(&self, r: &Ray) -> HitInfo {
+        stats::count(Counter::Intersections, self.kind.triangles().max(1) as u64);
+        let Some((_, inv)) = self.to_world else { return self.kind.try_ray(r) };
+
+        let lr = Ray::new(inv.transform_point(&r.origin.into()).coords, inv.transform_vector(&r.direction));
+        let mut hi = self.kind.try_ray(&lr);
+        if !hi.hit() { return hi }
+        hi.p = r.at(hi.t);
+        hi.n = (inv.fixed_view::<3, 3>(0, 0).transpose() * hi.n).normalize();
+        hi
+    }
+
+    pub fn get_random_point(&self, smp: &mut Sampler) -> Vector3<f64> {
+        let p = self.kind.get_random_point(smp);
+        match self.to_world {
+            Some((m, _)) => m.transform_point(&p.into()).coords,
+            None => p,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Transform {
+    pub pos: Vector3<f64>,
+    pub rot: Vector3<f64>,
+    pub scale: Vector3<f64>,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self { pos: Vector3::default(), rot: Vector3::default(), scale: Vector3::new(1.0, 1.0, 1.0) }
+    }
+}
+
+impl Transform {
+    // rotation and scale are around `pivot`, `pos` is an offset from where the object was built
+    pub fn matrix(&self, pivot: Vector3<f64>) -> Matrix4<f64> {
+        Matrix4::new_translation(&(self.pos + pivot))
+            * Rotation3::from_euler_angles(self.rot[0], self.rot[1], self.rot[2]).to_homogeneous()
+            * Matrix4::new_nonuniform_scaling(&self.scale)
+            * Matrix4::new_translation(&-pivot)
+    }
+}
+
+#[derive(Clone, PartialEq)]
 pub struct Material {
     pub color: Vector3<f64>,
     pub emit_color: Vector3<f64>,
@@ -51,19 +355,32 @@ impl Default for Material {
 
 pub trait ObjectKind: Sync + Send {
     fn try_ray(&self, ray: &Ray) -> HitInfo;
-    fn get_random_point(&self, rng: &mut ThreadRng) -> Vector3<f64>;
+    fn get_random_point(&self, smp: &mut Sampler) -> Vector3<f64>;
+    fn center(&self) -> Vector3<f64>;
+    fn name(&self) -> &'static str;
+    fn triangles(&self) -> usize;
 }
 
-// #[derive(Default)]
+// hits closer than this to the origin are taken to be the surface the ray started from
+pub const T_MIN: f64 = 0.001;
+
+#[derive(Clone, Copy)]
 pub struct HitInfo {
     pub p: Vector3<f64>,
     pub n: Vector3<f64>,
     pub t: f64,
 }
 
+// a miss, every kind returns this one
 impl Default for HitInfo {
     fn default() -> Self {
-        Self { p: Vector3::default(), n: Vector3::default(), t: -0.001 }
+        Self { p: Vector3::default(), n: Vector3::default(), t: -1.0 }
+    }
+}
+
+impl HitInfo {
+    pub fn hit(&self) -> bool {
+        self.t > T_MIN
     }
 }
 
@@ -89,49 +406,305 @@ pub fn rotate(p: Vector3<f64>, r: Vector2<f64>) -> Vector3<f64> {
     rt
 }
 
-pub fn render(rs: &mut RendererState, size: usize, prev_img: &mut Vec<Vec<Vector3<f64>>>, passes_done: usize) -> Vec<Vec<(u8, u8, u8)>> {
-    let scr_f = prev_img;
-    let scr_i = vec![vec![(0, 0, 0); size]; size];
-    scr_f.into_par_iter().enumerate().for_each(|(ay, scr_f)| {
-        for ax in 0..size {
-            let x = (size - ax - 1) as f64;
-            let y = (size - ay - 1) as f64;
+// one whole pass, returns the image as it looks now
+pub fn render(rs: &mut RendererState, size: usize, prev_img: &mut [Vec<Pixel>], passes_done: usize) -> Vec<Vec<(u8, u8, u8)>> {
+    let tiles = tiles(size, size);
+    rs.active_pixels = render_tiles(rs, size, size, prev_img, passes_done, &tiles);
+    preview(rs, prev_img)
+}
 
-            let mut c = Vector3::default();
+// the corners of the tiles, in a spiral from the middle out so the interesting part of the image shows up first
+pub fn tiles(w: usize, h: usize) -> Vec<(usize, usize)> {
+    let (nx, ny) = (w.div_ceil(TILE_SIZE) as isize, h.div_ceil(TILE_SIZE) as isize);
+    let mut t: Vec<(isize, isize)> = (0..ny).flat_map(|y| (0..nx).map(move |x| (x, y))).collect();
+    t.sort_by(|a, b| {
+        let key = |(x, y): (isize, isize)| {
+            let (dx, dy) = (2 * x + 1 - nx, 2 * y + 1 - ny);
+            (dx.abs().max(dy.abs()), (dy as f64).atan2(dx as f64))
+        };
+        let (ka, kb) = (key(*a), key(*b));
+        ka.0.cmp(&kb.0).then(ka.1.total_cmp(&kb.1))
+    });
+    t.into_iter().map(|(x, y)| (x as usize * TILE_SIZE, y as usize * TILE_SIZE)).collect()
+}
 
-            for _ in 0..SAMPLES_LVL {
-                let px = x / size as f64 * 2.0 - 1.0;
-                let py = y / size as f64 * 2.0 - 1.0;
+// part of a pass, returns how many pixels got samples. tiles that haven't started when `rs.cancel` is set are skipped
+pub fn render_tiles(rs: &RendererState, w: usize, h: usize, prev_img: &mut [Vec<Pixel>], passes_done: usize, tiles: &[(usize, usize)]) -> usize {
+    let material_ids = material_ids(&rs.scene);
+    let prev = &*prev_img;
+    // how many pixels next to its own a sample can reach
+    let reach = (rs.filter.radius() - 0.5).ceil().max(0.0) as usize;
+
+    // every tile splats into a buffer of its own, and they're added to the image in order afterwards,
+    // so nothing depends on which thread finished first
+    let start = Instant::now();
+    let tiles: Vec<_> = tiles.par_iter().map(|&(x0, y0)| {
+        let (x1, y1) = ((x0 + TILE_SIZE).min(w), (y0 + TILE_SIZE).min(h));
+        let (fx, fy) = (x0.saturating_sub(reach), y0.saturating_sub(reach));
+        if rs.cancel.load(Ordering::Relaxed) { return ((fx, fy), Vec::new(), 0) }
+
+        let mut tile = vec![vec![Pixel::default(); (x1 + reach).min(w) - fx]; (y1 + reach).min(h) - fy];
+        let mut active = 0;
+
+        for ay in y0..y1 {
+            for ax in x0..x1 {
+                if rs.noise_threshold.is_some_and(|t| prev[ay][ax].error() < t) { continue }
+                active += 1;
+
+                for si in 0..SAMPLES_LVL {
+                    let smp = &mut Sampler::new(rs.sampler, rs.seed, ax, ay, passes_done, si, SAMPLES_LVL);
+
+                    // somewhere inside the pixel, the screen is flipped in both directions and wider images see more to the sides
+                    let j = smp.get2d();
+                    let (sx, sy) = (ax as f64 + j[0], ay as f64 + j[1]);
+                    let px = ((w as f64 - sx) / w as f64 * 2.0 - 1.0) * (w as f64 / h as f64);
+                    let py = (h as f64 - sy) / h as f64 * 2.0 - 1.0;
+
+                    let lens = sampler::disc(smp.get2d());
+                    let ray_pos = rs.cam_pos + Vector3::new(lens[0], lens[1], 0.0) * (0.05 * rs.aperture);
+
+                    let ray_tar = Vector3::new(px, py, 1.0) * rs.focus.max(0.05) - ray_pos;
+
+                    let ray_dir = rotate((ray_tar + rs.cam_pos).normalize(), rs.rot);
+
+                    let ray = Ray::new(ray_pos, ray_dir);
+
+                    let r = ray.get_color(rs, 0, Vector3::new(1.0, 1.0, 1.0), smp);
+                    let beauty = apply_light(r.c, r.l) * (1.0 / r.p);
+                    let emission = apply_light(r.c, r.emit) * (1.0 / r.p);
+                    let direct = apply_light(r.c, r.direct) * (1.0 / r.p);
+
+                    let mut c = Pixel {
+                        color: beauty,
+                        emission,
+                        direct,
+                        indirect: beauty - emission - direct,
+                        ..Default::default()
+                    };
+                    match r.hit {
+                        Some((h, i)) => {
+                            c.depth = h.t;
+                            c.normal = h.n;
+                            c.albedo = rs.scene[i].material.color;
+                            c.object = i as f64;
+                            c.material = material_ids[i] as f64;
+                        },
+                        None => c.albedo = r.c,
+                    }
+
+                    for qy in ay.saturating_sub(reach)..(ay + reach + 1).min(h) {
+                        let qx0 = ax.saturating_sub(reach);
+                        for (qx, q) in tile[qy - fy][qx0 - fx..(ax + reach + 1).min(w) - fx].iter_mut().enumerate() {
+                            let w = rs.filter.weight((qx0 + qx) as f64 + 0.5 - sx, qy as f64 + 0.5 - sy);
+                            if w != 0.0 { q.splat(&c, w) }
+                        }
+                    }
+                    let p = &mut tile[ay - fy][ax - fx];
+                    let l = crate::tonemap::luminance(&c.color);
+                    p.samples += 1.0;
+                    p.lum += l;
+                    p.lum2 += l * l;
+                    if c.object >= 0.0 {
+                        p.object = c.object;
+                        p.material = c.material;
+                    }
+                }
+            }
+        }
+        rs.stats.flush();
+        ((fx, fy), tile, active)
+    }).collect();
+    rs.stats.time(Phase::Trace, start.elapsed());
+
+    let start = Instant::now();
+
+    let active = tiles.iter().map(|t| t.2).sum();
+    for ((fx, fy), tile, _) in tiles {
+        for (row, t) in prev_img[fy..].iter_mut().zip(tile) {
+            for (p, t) in row[fx..].iter_mut().zip(t) {
+                p.add(&t);
+            }
+        }
+    }
+    rs.stats.time(Phase::Merge, start.elapsed());
+    active
+}
 
-                let ray_pos = rs.cam_pos + generate_random_circle() * (0.05 * rs.aperture);
+#[derive(Clone, Copy, Debug)]
+pub struct Progress {
+    pub pass: usize,
+    pub passes: usize,
+    // tiles of every pass together
+    pub tiles_done: usize,
+    pub tiles: usize,
+    pub elapsed: Duration,
+}
 
-                let ray_tar = Vector3::new(px, py, 1.0) * rs.focus.max(0.05) - ray_pos;
+impl Progress {
+    pub fn fraction(&self) -> f64 {
+        self.tiles_done as f64 / self.tiles.max(1) as f64
+    }
 
-                let ray_dir = rotate((ray_tar + rs.cam_pos).normalize(), rs.rot);
+    // assumes the rest goes as fast as what's done so far
+    pub fn eta(&self) -> Option<Duration> {
+        if self.tiles_done == 0 { return None }
+        Some(self.elapsed.mul_f64((self.tiles - self.tiles_done) as f64 / self.tiles_done as f64))
+    }
+}
 
-                let ray = Ray::new(ray_pos, ray_dir);
+// `passes` whole passes, with `progress` called after every round of tiles so it can show them filling in.
+// setting `rs.cancel` stops it early, and then it returns false
+pub fn render_progress(rs: &RendererState, w: usize, h: usize, img: &mut [Vec<Pixel>], passes: usize, mut progress: impl FnMut(&Progress, &[Vec<Pixel>])) -> bool {
+    let tiles = tiles(w, h);
+    let start = Instant::now();
 
-                let r = ray.get_color(&rs.scene, 0, Vector3::new(1.0, 1.0, 1.0));
-                c += apply_light(r.0, r.1) * (1.0 / r.2);
-            }
+    for pass in 1..=passes {
+        for (i, round) in tiles.chunks(rayon::current_num_threads()).enumerate() {
+            if rs.cancel.load(Ordering::Relaxed) { return false }
+            render_tiles(rs, w, h, img, pass, round);
 
-            c /= SAMPLES_LVL as f64;
+            let done = (pass - 1) * tiles.len() + (i * rayon::current_num_threads() + round.len());
+            progress(&Progress { pass, passes, tiles_done: done, tiles: passes * tiles.len(), elapsed: start.elapsed() }, img);
+        }
+    }
+    !rs.cancel.load(Ordering::Relaxed)
+}
 
-            scr_f[ax] += c;
-            c = scr_f[ax] / passes_done as f64;
-            
-            let scr_i = unsafe {
-                &mut *(&scr_i
-                    as *const Vec<Vec<(u8, u8, u8)>>
-                    as *mut Vec<Vec<(u8, u8, u8)>>)
-            };
-            scr_i[ay][ax] = (map(c[0]), map(c[1]), map(c[2]));
+// the beauty pass as it would be shown
+pub fn preview(rs: &RendererState, img: &[Vec<Pixel>]) -> Vec<Vec<(u8, u8, u8)>> {
+    img.par_iter().map(|row| row.iter().map(|p| rs.tonemap.rgb8(p.get(Aov::Beauty))).collect()).collect()
+}
+
+// renders as much as fits into a time budget and carries on where it stopped the next time, so a slow scene
+// still gets a responsive viewer and a fast one isn't held back to one pass per frame
+#[derive(Default)]
+pub struct Progressive {
+    // finished passes, and the next tile of the one after them
+    pub passes: usize,
+    pub tile: usize,
+    // the pixels of the pass so far that got samples
+    active: usize,
+    // every pixel was below the noise threshold in the last pass
+    pub converged: bool,
+    // how long a tile took, averaged over the last few
+    tile_time: Option<f64>,
+}
+
+impl Progressive {
+    pub fn resume(passes: usize) -> Self {
+        Self { passes, ..Default::default() }
+    }
+
+    // passes that have at least started, which is what a checkpoint of the image holds
+    pub fn started(&self) -> usize {
+        self.passes + (self.tile > 0) as usize
+    }
+
+    // stops when the budget is spent or at the end of a pass, whichever comes first, but always renders something
+    pub fn render(&mut self, rs: &mut RendererState, size: usize, img: &mut [Vec<Pixel>], budget: Duration) {
+        if self.converged { return }
+
+        let start = Instant::now();
+        let tiles = tiles(size, size);
+        let total = tiles.len();
+        loop {
+            // as many tiles as should fit in what's left, and a round for every thread before anything is known
+            let left = budget.saturating_sub(start.elapsed()).as_secs_f64();
+            let n = match self.tile_time {
+                Some(t) => (left / t) as usize,
+                None => rayon::current_num_threads(),
+            }.clamp(1, total - self.tile);
+
+            let t = Instant::now();
+            self.active += render_tiles(rs, size, size, img, self.passes + 1, &tiles[self.tile..self.tile + n]);
+            let per_tile = t.elapsed().as_secs_f64() / n as f64;
+            self.tile_time = Some(self.tile_time.map_or(per_tile, |b| b * 0.5 + per_tile * 0.5));
+
+            self.tile += n;
+            if self.tile == total {
+                self.passes += 1;
+                self.tile = 0;
+                rs.active_pixels = std::mem::take(&mut self.active);
+                self.converged = rs.noise_threshold.is_some() && rs.active_pixels == 0;
+                return;
+            }
+            if start.elapsed() >= budget { return }
         }
-    });
-    scr_i
+    }
+}
+
+// the ray through the middle of a pixel, without any lens blur
+pub fn camera_ray(rs: &RendererState, size: usize, ax: usize, ay: usize) -> Ray {
+    let x = (size - ax - 1) as f64 + 0.5;
+    let y = (size - ay - 1) as f64 + 0.5;
+    let px = x / size as f64 * 2.0 - 1.0;
+    let py = y / size as f64 * 2.0 - 1.0;
+
+    Ray::new(rs.cam_pos, rotate(Vector3::new(px, py, 1.0), rs.rot))
+}
+
+// objects with equal materials share an id, numbered in the order they first show up
+fn material_ids(scene: &[Object]) -> Vec<usize> {
+    let mut seen: Vec<&Material> = Vec::new();
+    scene.iter().map(|o| match seen.iter().position(|m| **m == o.material) {
+        Some(i) => i,
+        None => {
+            seen.push(&o.material);
+            seen.len() - 1
+        },
+    }).collect()
+}
+
+// one buffer out of an accumulation buffer, divided by the filter weights
+pub fn layer(img: &[Vec<Pixel>], aov: Aov) -> Vec<Vec<Vector3<f64>>> {
+    img.iter().map(|row| row.iter().map(|p| p.get(aov)).collect()).collect()
+}
+
+// the beauty pass alone, or every buffer
+pub fn layers(img: &[Vec<Pixel>], all: bool) -> Vec<(Aov, Vec<Vec<Vector3<f64>>>)> {
+    AOVS.iter().take(if all { AOVS.len() } else { 1 }).map(|(a, _)| (*a, layer(img, *a))).collect()
+}
+
+// turns any buffer into display colors in 0..1, light goes through the tone mapping and the rest is made visible
+pub fn visualize(aov: Aov, img: &[Vec<Vector3<f64>>], tm: &crate::tonemap::Tonemap) -> Vec<Vec<Vector3<f64>>> {
+    let far = img.iter().flatten().map(|v| v[0]).fold(0.0, f64::max).max(1e-9);
+    img.iter().map(|row| row.iter().map(|v| match aov {
+        _ if aov.radiance() => tm.apply(*v),
+        Aov::Depth if v[0] <= 0.0 => Vector3::default(),
+        Aov::Depth => Vector3::repeat(1.0 - v[0] / far),
+        Aov::Normal => (v * 0.5).add_scalar(0.5),
+        Aov::Samples => heatmap(v[0] / far),
+        Aov::ObjectId | Aov::MaterialId if v[0] < 0.0 => Vector3::default(),
+        Aov::ObjectId | Aov::MaterialId => {
+            // a hash of the id so neighbouring ids get unrelated colors
+            let h = (v[0] as u64 + 1).wrapping_mul(0x9E3779B97F4A7C15);
+            Vector3::new((h >> 40) as u8, (h >> 48) as u8, (h >> 56) as u8).map(|c| c as f64 / 255.0)
+        },
+        _ => v.map(crate::tonemap::srgb),
+    }.map(|c| c.clamp(0.0, 1.0))).collect()).collect()
+}
+
+// black through red and yellow to white as `t` goes from 0 to 1
+pub fn heatmap(t: f64) -> Vector3<f64> {
+    Vector3::new(t * 3.0, t * 3.0 - 1.0, t * 3.0 - 2.0).map(|c| c.clamp(0.0, 1.0))
+}
+
+pub fn to_rgb8(img: &[Vec<Vector3<f64>>]) -> Vec<Vec<(u8, u8, u8)>> {
+    img.iter().map(|r| r.iter().map(|v| {
+        let c = v.map(|c| (c * 255.0 + 0.5) as u8);
+        (c[0], c[1], c[2])
+    }).collect()).collect()
+}
+
+pub fn render_passes(rs: &mut RendererState, size: usize, passes: usize) -> Vec<Vec<Pixel>> {
+    let mut img = vec![vec![Pixel::default(); size]; size];
+    for i in 1..=passes {
+        render(rs, size, &mut img, i);
+        if rs.active_pixels == 0 { break }
+    }
+    img
 }
 
-fn map(v: f64) -> u8 { (v.sqrt() * 255.0).min(255.0) as u8 }
 
 pub struct Sphere {
     pub c: Vector3<f64>,
@@ -146,21 +719,27 @@ impl ObjectKind for Sphere {
         let c = o.dot(&o) - self.r * self.r;
         let d = b * b - 4.0 * a * c;
 
-        let mut hi = HitInfo::default();
-        if d >= 0.0 {
-            hi.t = (-b - d.sqrt()) / (2.0 * a);
-            hi.p = r.at(hi.t);
-            hi.n = (hi.p - self.c) / self.r;
-        }
+        if d < 0.0 { return HitInfo::default() }
 
-        hi
+        // the far root when the near one is behind the origin, which is inside then
+        let q = -0.5 * (b + b.signum() * d.sqrt());
+        let (t0, t1) = if q == 0.0 { (0.0, 0.0) } else { (q / a, c / q) };
+        let Some(t) = [t0.min(t1), t0.max(t1)].into_iter().find(|t| *t > T_MIN) else { return HitInfo::default() };
+
+        let p = r.at(t);
+        HitInfo { p, n: (p - self.c) / self.r, t }
     }
-    fn get_random_point(&self, _rng: &mut ThreadRng) -> Vector3<f64> {
-        generate_random_sphere() * self.r + self.c
+    fn get_random_point(&self, smp: &mut Sampler) -> Vector3<f64> {
+        sampler::sphere(smp.get2d()) * self.r + self.c
     }
+    fn center(&self) -> Vector3<f64> {
+        self.c
+    }
+    fn name(&self) -> &'static str { "Sphere" }
+    fn triangles(&self) -> usize { 0 }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Triangle {
     pub vp: [Vector3<f64>; 3],
     pub vn: Option<[Vector3<f64>; 3]>,
@@ -175,38 +754,53 @@ impl ObjectKind for Triangle {
         let pvec = r.direction.cross(&v0v2);
         let det = v0v1.dot(&pvec);
 
-        if det < 0.0 { return hi }
+        if det <= 0.0 { return hi }
 
         let inv_det = 1.0 / det;
 
         let tvec = r.origin - self.vp[0];
         let u = tvec.dot(&pvec) * inv_det;
-        if u < 0.0 || u > 1.0 { return hi }
+        if !(0.0..=1.0).contains(&u) { return hi }
 
         let qvec = tvec.cross(&v0v1);
         let v = r.direction.dot(&qvec) * inv_det;
         if v < 0.0 || u+v > 1.0 { return hi }
 
+        // a ray along the plane or a triangle without an area gets this far with u and v made of rounding errors.
+        // det is the cosine between the ray and the normal times both their lengths, so it's the angle that counts
+        let n = v0v1.cross(&v0v2);
+        if det * det <= PARALLEL * PARALLEL * n.norm_squared() * r.direction.norm_squared() { return hi }
+
         let t = v0v2.dot(&qvec) * inv_det;
-        
+        if t <= T_MIN { return hi }
+
         hi.t = t;
         hi.p = r.at(t);
         hi.n = match self.vn {
-            Some(vn) => (1.0-u-v) * vn[0] + u * vn[1] + v * vn[2],
-            None => v0v1.cross(&v0v2).normalize(),
+            Some(vn) => ((1.0-u-v) * vn[0] + u * vn[1] + v * vn[2]).normalize(),
+            None => n.normalize(),
         };
 
         hi
     }
-    fn get_random_point(&self, rng: &mut ThreadRng) -> Vector3<f64> {
-        let u = rng.gen_range(0.0..1.0);
-        let v = rng.gen_range(0.0..1.0-u);
+    fn get_random_point(&self, smp: &mut Sampler) -> Vector3<f64> {
+        let b = sampler::triangle(smp.get2d());
+        let (u, v) = (b[0], b[1]);
         let w = 1.0 - u - v;
 
         self.vp[0] * w + self.vp[1] * u + self.vp[2] * v
     }
+    fn center(&self) -> Vector3<f64> {
+        (self.vp[0] + self.vp[1] + self.vp[2]) / 3.0
+    }
+    fn name(&self) -> &'static str { "Triangle" }
+    fn triangles(&self) -> usize { 1 }
 }
 
+// the cosine below which a ray counts as running along a triangle
+const PARALLEL: f64 = 1e-9;
+
+#[derive(Clone)]
 pub struct Mesh {
     pub ts: Vec<Triangle>
 }
@@ -217,16 +811,33 @@ impl ObjectKind for Mesh {
         let mut t = f64::INFINITY;
         for i in self.ts.iter() {
             let h = i.try_ray(r);
-            if h.t > 0.001 && h.t < t {
+            if h.hit() && h.t < t {
                 t = h.t;
                 fhi = Some(h)
             }
         }
-        fhi.unwrap_or(HitInfo { p: Vector3::default(), n: Vector3::default(), t: -1.0 })
+        fhi.unwrap_or_default()
+    }
+    fn get_random_point(&self, smp: &mut Sampler) -> Vector3<f64> {
+        let i = (smp.get1d() * self.ts.len() as f64) as usize;
+        self.ts[i.min(self.ts.len() - 1)].get_random_point(smp)
     }
-    fn get_random_point(&self, rng: &mut ThreadRng) -> Vector3<f64> {
-        self.ts[rng.gen_range(0..self.ts.len())].get_random_point(rng)
+    fn center(&self) -> Vector3<f64> {
+        self.ts.iter().map(|t| t.center()).sum::<Vector3<f64>>() / self.ts.len().max(1) as f64
     }
+    fn name(&self) -> &'static str { "Mesh" }
+    fn triangles(&self) -> usize { self.ts.len() }
+}
+
+// what one path gave back: color and light (multiplied together for the pixel), the chance of it not having
+// been cut short, the part of the light emitted where it hit and the part that came straight from the next hit
+pub struct Shade {
+    pub c: Vector3<f64>,
+    pub l: Vector3<f64>,
+    pub p: f64,
+    pub emit: Vector3<f64>,
+    pub direct: Vector3<f64>,
+    pub hit: Option<(HitInfo, usize)>,
 }
 
 pub struct Ray {
@@ -243,12 +854,22 @@ impl Ray {
         self.origin + self.direction * t
     }
 
-    pub fn try_hit<'a>(&self, scene: &'a Vec<Object<'a>>) -> Option<(HitInfo, &'a Object<'a>)> {
+    pub fn try_hit<'a>(&self, scene: &'a [Object]) -> Option<(HitInfo, &'a Object)> {
+        self.try_hit_index(scene).map(|(h, i)| (h, &scene[i]))
+    }
+
+    pub fn try_hit_index(&self, scene: &[Object]) -> Option<(HitInfo, usize)> {
+        self.try_hit_where(scene, |o| o.visible)
+    }
+
+    pub fn try_hit_where(&self, scene: &[Object], f: impl Fn(&Object) -> bool) -> Option<(HitInfo, usize)> {
         let mut r = None;
         let mut t = f64::INFINITY;
-        for i in scene {
-            let h = i.kind.try_ray(self);
-            if h.t > 0.001 && h.t < t {
+        for (i, o) in scene.iter().enumerate() {
+            if !f(o) { continue }
+
+            let h = o.try_ray(self);
+            if h.hit() && h.t < t {
                 t = h.t;
                 r = Some((h, i))
             }
@@ -256,102 +877,84 @@ impl Ray {
         r
     }
 
-    pub fn get_color<'a>(&self, s: &Vec<Object<'a>>, i: usize, tp: Vector3<f64>) -> (Vector3<f64>, Vector3<f64>, f64) {
-        if i == LIGHT_BOUNCES {
-            return (
-                Vector3::default(),
-                Vector3::default(),
-                1.0
-            )
+    pub fn get_color(&self, rs: &RendererState, i: usize, tp: Vector3<f64>, smp: &mut Sampler) -> Shade {
+        let s = &rs.scene;
+        if i == rs.bounces {
+            return Shade {
+                c: Vector3::default(),
+                l: Vector3::default(),
+                p: 1.0,
+                emit: Vector3::default(),
+                direct: Vector3::default(),
+                hit: None,
+            }
         }
 
-        let h = self.try_hit(s);
-        if h.is_some() {
-            let (h, o) = h.unwrap();
+        stats::count(if i == 0 { Counter::Primary } else { Counter::Bounce }, 1);
+        let h = self.try_hit_index(s);
+        if let Some((h, oi)) = h {
+            let o = &s[oi];
 
             let p = tp.x.max(tp.y.max(tp.z));
             if i != 0 {
                 let c = o.material.color * (1.0 - o.material.shininess);
-                let l = o.material.emit_color * (1.0 - (h.t.abs() / (h.t.abs() + 100.0)));
-                let rn = rand::thread_rng().gen_range(0.0..1.0);
+                let l = o.emission() * (1.0 - (h.t.abs() / (h.t.abs() + 100.0)));
+                let rn = smp.get1d();
                 if rn > p {
-                    return (c, l, p)
+                    stats::count(Counter::Roulette, 1);
+                    return Shade { c, l, p, emit: l, direct: Vector3::default(), hit: Some((h, oi)) }
                 }
             }
 
-            let nee_dir = generate_random_light(s, h.p);
+            let nee_dir = generate_random_light(s, h.p, smp);
             let nee_ray = Ray::new(h.p, nee_dir);
-            let nee = nee_ray.try_hit(s);
-            let nee_l = if nee.is_some() {
-                let (h, o) = nee.unwrap();
-                o.material.emit_color * (1.0 - (h.t.abs() / (h.t.abs() + 100.0))) * 0.25
+            stats::count(Counter::Shadow, 1);
+            let blocks = |x: &Object| x.visible && (x.casts_shadow || x.emits_light);
+            let nee = nee_ray.try_hit_where(s, |x| !std::ptr::eq(x, o) && blocks(x)).map(|(h, i)| (h, &s[i]));
+            // the surface it starts on only counts from the outside, or a sphere would shadow itself wherever the ray heads into it
+            let own = if blocks(o) { o.try_ray(&nee_ray) } else { HitInfo::default() };
+            let nee = match nee {
+                _ if own.hit() && own.n.dot(&nee_dir) < 0.0 && nee.is_none_or(|(h, _)| own.t < h.t) => Some((own, o)),
+                nee => nee,
+            };
+            let nee_l = if let Some((h, o)) = nee {
+                o.emission() * (1.0 - (h.t.abs() / (h.t.abs() + 100.0))) * 0.25
             } else { Vector3::new(1.0, 1.0, 1.0) };
             let nee_lc = Vector3::new(1.0, 1.0, 1.0).lerp(&nee_l, o.material.shininess);
 
             let specular_dir = self.direction - 2.0 * self.direction.dot(&h.n) * h.n;
-            let diffuse_dir  = h.n + generate_random_sphere().normalize();
+            let diffuse_dir  = sampler::hemisphere(h.n, smp.get2d());
 
             let indirect_ray = Ray::new(h.p, specular_dir.lerp(&diffuse_dir, o.material.rough));
-            let srr = indirect_ray.get_color(s, i+1, tp * (1.0 - o.material.shininess * 0.5) * 1.0 / p);
+            let srr = indirect_ray.get_color(rs, i+1, tp * (1.0 - o.material.shininess * 0.5) * 1.0 / p, smp);
 
-            let c = srr.0 * o.material.shininess + o.material.color * (1.0 - o.material.shininess);
-            let mut l = srr.1 * (0.35 + o.material.shininess * (1.0 - 0.35)) + o.material.emit_color;
+            let c = srr.c * o.material.shininess + o.material.color * (1.0 - o.material.shininess);
+            let k = 0.35 + o.material.shininess * (1.0 - 0.35);
             let lm = nee_lc * (1.0 - (h.t.abs() / (h.t.abs() + 100.0))) * 0.75;
 
-            l.x *= lm.x;
-            l.y *= lm.y;
-            l.z *= lm.z;
+            // light from this surface itself, and light that reached it straight from the next hit
+            let emit = o.emission().component_mul(&lm);
+            let direct = (srr.emit * k).component_mul(&lm);
+            let l = (srr.l * k).component_mul(&lm) + emit;
 
-            (
-                c, l, 1.0
-            )
+            Shade { c, l, p: 1.0, emit, direct, hit: Some((h, oi)) }
         } else {
             let t = 0.5 * (self.direction[1] + 1.0);
             let sc = (1.0 - t) * Vector3::new(1.0, 1.0, 1.0) + t * Vector3::new(0.5, 0.7, 1.0);
-            (sc, SKY_LIGHT * (t + 0.5), 1.0)
+            let l = rs.sky_light * (t + 0.5);
+            Shade { c: sc, l, p: 1.0, emit: l, direct: Vector3::default(), hit: None }
         }
     }
 }
 
-fn generate_random_light<'a>(s: &Vec<Object<'a>>, from: Vector3<f64>) -> Vector3<f64> {
-    let mut rng = rand::thread_rng();
-    for _ in 0..RNG_LIMIT {
-        let o = &s[rng.gen_range(0..s.len())];
-        if o.material.emit_color.sum() < 0.25 { continue }
+fn generate_random_light(s: &[Object], from: Vector3<f64>, smp: &mut Sampler) -> Vector3<f64> {
+    let is_light = |o: &&Object| o.visible && o.emission().sum() >= 0.25;
+    let n = s.iter().filter(is_light).count();
+    if n == 0 { return Vector3::y() }
 
-        return (from - o.kind.get_random_point(&mut rng)).normalize();
-    }
-    Vector3::y()
-}
-
-fn generate_random_sphere() -> Vector3<f64> {
-    let mut rng = rand::thread_rng();
-    for _ in 0..RNG_LIMIT {
-        let p = Vector3::new(
-            rng.gen_range(-1.0..1.0),
-            rng.gen_range(-1.0..1.0),
-            rng.gen_range(-1.0..1.0),
-        );
-        if (p[0] * p[0] + p[1] * p[1] + p[2] * p[2]) < 1.0 {
-            return p;
-        }
-    }
-    Vector3::default()
-}
-
-fn generate_random_circle() -> Vector3<f64> {
-    let mut rng = rand::thread_rng();
-    for _ in 0..RNG_LIMIT {
-        let p = Vector3::new(
-            rng.gen_range(-1.0..1.0),
-            rng.gen_range(-1.0..1.0),
-            0.0,
-        );
-        if (p[0] * p[0] + p[1] * p[1]) < 1.0 {
-            return p;
-        }
-    }
-    Vector3::default()
+    let i = ((smp.get1d() * n as f64) as usize).min(n - 1);
+    let o = s.iter().filter(is_light).nth(i).unwrap();
+    (from - o.get_random_point(smp)).normalize()
 }
 
 fn apply_light(c: Vector3<f64>, l: Vector3<f64>) -> Vector3<f64> {
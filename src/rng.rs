@@ -0,0 +1,59 @@
+use rand::RngCore;
+
+// PCG32 (XSH RR), small and fast enough to make one for every sample
+#[derive(Clone, Debug)]
+pub struct Pcg {
+    state: u64,
+    inc: u64,
+}
+
+impl Pcg {
+    pub fn new(seed: u64, stream: u64) -> Self {
+        let mut r = Self { state: 0, inc: (stream << 1) | 1 };
+        r.next_u32();
+        r.state = r.state.wrapping_add(seed);
+        r.next_u32();
+        r
+    }
+
+    // every sample of every pixel gets its own sequence, so the image doesn't depend on which thread rendered what
+    pub fn for_sample(seed: u64, pass: usize, x: usize, y: usize, sample: usize) -> Self {
+        Self::new(hash(&[seed, pass as u64, sample as u64]), hash(&[x as u64, y as u64]))
+    }
+}
+
+impl RngCore for Pcg {
+    fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old.wrapping_mul(6364136223846793005).wrapping_add(self.inc);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        xorshifted.rotate_right((old >> 59) as u32)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        (self.next_u32() as u64) << 32 | self.next_u32() as u64
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for c in dest.chunks_mut(4) {
+            c.copy_from_slice(&self.next_u32().to_le_bytes()[..c.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+// splitmix64 folded over the values
+pub fn hash(v: &[u64]) -> u64 {
+    let mut h: u64 = 0;
+    for v in v {
+        h = (h ^ v).wrapping_add(0x9E3779B97F4A7C15);
+        h = (h ^ (h >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        h = (h ^ (h >> 27)).wrapping_mul(0x94D049BB133111EB);
+        h ^= h >> 31;
+    }
+    h
+}
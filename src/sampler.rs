@@ -0,0 +1,278 @@
+use nalgebra::base::*;
+use rand::Rng;
+use std::f64::consts::{PI, TAU};
+use std::sync::OnceLock;
+
+use crate::rng::{Pcg, hash};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Kind {
+    // plain random numbers, like the renderer always used
+    Independent,
+    // each pass is split into a grid of strata, shuffled separately for every dimension
+    Stratified,
+    Halton,
+    // Owen scrambled, which keeps the low discrepancy but makes neighbouring pixels unrelated
+    #[default]
+    Sobol,
+    // one Sobol sequence shared by every pixel, shifted by a blue noise tile so the error looks like fine grain
+    BlueNoise,
+}
+
+pub const KINDS: &[(Kind, &str)] = &[
+    (Kind::Independent, "independent"),
+    (Kind::Stratified,  "stratified"),
+    (Kind::Halton,      "halton"),
+    (Kind::Sobol,       "sobol"),
+    (Kind::BlueNoise,   "blue_noise"),
+];
+
+impl Kind {
+    pub fn name(&self) -> &'static str {
+        KINDS.iter().find(|k| k.0 == *self).unwrap().1
+    }
+
+    pub fn parse(s: &str) -> Result<Self, String> {
+        KINDS.iter().find(|k| k.1 == s).map(|k| k.0).ok_or_else(|| {
+            format!("unknown sampler `{s}`, expected one of {}", KINDS.iter().map(|k| k.1).collect::<Vec<_>>().join(", "))
+        })
+    }
+}
+
+const PRIMES: [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53,
+    59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131,
+];
+
+const BLUE_NOISE_SIZE: usize = 64;
+
+// the numbers for one sample of one pixel. every call uses up the next dimension, so a path asks for them in the same
+// order every time and the sequences line up between samples
+pub struct Sampler {
+    kind: Kind,
+    x: usize,
+    y: usize,
+    // which sample of the pixel this is, counted over every pass
+    index: u32,
+    // samples per pass, which stratified sampling splits into strata
+    count: u32,
+    dim: u64,
+    seed: u64,
+    rng: Pcg,
+}
+
+impl Sampler {
+    pub fn new(kind: Kind, seed: u64, x: usize, y: usize, pass: usize, sample: usize, count: usize) -> Self {
+        Self {
+            kind, x, y,
+            index: (pass.saturating_sub(1) * count + sample) as u32,
+            count: count as u32,
+            dim: 0,
+            // blue noise shares one sequence between all pixels
+            seed: if kind == Kind::BlueNoise { hash(&[seed]) } else { hash(&[seed, x as u64, y as u64]) },
+            rng: Pcg::for_sample(seed, pass, x, y, sample),
+        }
+    }
+
+    pub fn get1d(&mut self) -> f64 {
+        let d = self.next_dim();
+        match self.kind {
+            Kind::Independent => self.rng.gen(),
+            Kind::Stratified => {
+                let s = permute(self.index % self.count, self.count, hash(&[self.seed, (self.index / self.count) as u64, d]) as u32);
+                (s as f64 + self.rng.gen::<f64>()) / self.count as f64
+            },
+            Kind::Halton if (d as usize) < PRIMES.len() => {
+                // shifted by a random amount per pixel, otherwise every pixel would get the same numbers
+                (radical_inverse(PRIMES[d as usize], self.index) + unit(hash(&[self.seed, d]) as u32)).fract()
+            },
+            Kind::Halton => self.rng.gen(),
+            Kind::Sobol => unit(sobol(self.index, self.seed, d).0),
+            Kind::BlueNoise => (unit(sobol(self.index, self.seed, d).0) + self.blue_noise(d, 0)).fract(),
+        }
+    }
+
+    pub fn get2d(&mut self) -> Vector2<f64> {
+        let side = (self.count as f64).sqrt() as u32;
+        match self.kind {
+            Kind::Independent => Vector2::new(self.rng.gen(), self.rng.gen()),
+            Kind::Stratified if side * side == self.count => {
+                let d = self.next_dim();
+                let s = permute(self.index % self.count, self.count, hash(&[self.seed, (self.index / self.count) as u64, d]) as u32);
+                Vector2::new(
+                    ((s % side) as f64 + self.rng.gen::<f64>()) / side as f64,
+                    ((s / side) as f64 + self.rng.gen::<f64>()) / side as f64,
+                )
+            },
+            Kind::Stratified | Kind::Halton => Vector2::new(self.get1d(), self.get1d()),
+            Kind::Sobol => {
+                let (a, b) = sobol(self.index, self.seed, self.next_dim());
+                Vector2::new(unit(a), unit(b))
+            },
+            Kind::BlueNoise => {
+                let d = self.next_dim();
+                let (a, b) = sobol(self.index, self.seed, d);
+                Vector2::new((unit(a) + self.blue_noise(d, 0)).fract(), (unit(b) + self.blue_noise(d, 1)).fract())
+            },
+        }
+    }
+
+    fn next_dim(&mut self) -> u64 {
+        self.dim += 1;
+        self.dim - 1
+    }
+
+    // the tile is looked up at a different offset for every dimension, so the dimensions don't share a pattern
+    fn blue_noise(&self, d: u64, c: u64) -> f64 {
+        let o = hash(&[d, c]) as usize;
+        let (x, y) = ((self.x + o) % BLUE_NOISE_SIZE, (self.y + (o >> 32)) % BLUE_NOISE_SIZE);
+        blue_noise_tile()[y * BLUE_NOISE_SIZE + x]
+    }
+}
+
+fn unit(v: u32) -> f64 {
+    v as f64 / (1u64 << 32) as f64
+}
+
+fn radical_inverse(base: u32, mut i: u32) -> f64 {
+    let (mut r, mut f) = (0.0, 1.0 / base as f64);
+    while i > 0 {
+        r += (i % base) as f64 * f;
+        i /= base;
+        f /= base as f64;
+    }
+    r
+}
+
+// the first two dimensions of the Sobol sequence, with the index shuffled differently for every dimension so
+// higher dimensions don't need their own direction numbers (Burley, Practical Hash-based Owen Scrambling)
+fn sobol(index: u32, seed: u64, dim: u64) -> (u32, u32) {
+    let h = |i: u64| hash(&[seed, dim, i]) as u32;
+    let i = owen_scramble(index, h(0));
+
+    let (mut a, mut b) = (0u32, 0u32);
+    let mut v = 1u32 << 31;
+    for bit in 0..32 {
+        if i >> bit & 1 == 1 {
+            a ^= 1 << (31 - bit);
+            b ^= v;
+        }
+        v ^= v >> 1;
+    }
+    (owen_scramble(a, h(1)), owen_scramble(b, h(2)))
+}
+
+fn owen_scramble(x: u32, seed: u32) -> u32 {
+    // Laine and Karras' hash, which only lets bits affect the bits above them
+    let mut x = x.reverse_bits().wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x.reverse_bits()
+}
+
+// a random permutation of 0..l without storing it, from Kensler's Correlated Multi-Jittered Sampling
+fn permute(mut i: u32, l: u32, p: u32) -> u32 {
+    let mut w = l.wrapping_sub(1);
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < l { break }
+    }
+    (i.wrapping_add(p)) % l
+}
+
+// built once with the void filling half of void-and-cluster: every pixel goes where the points so far are the
+// sparsest, and its rank becomes its value
+fn blue_noise_tile() -> &'static [f64] {
+    static TILE: OnceLock<Vec<f64>> = OnceLock::new();
+    TILE.get_or_init(|| {
+        const N: usize = BLUE_NOISE_SIZE;
+        const SIGMA: f64 = 1.9;
+
+        let kernel: Vec<f64> = (0..N * N).map(|i| {
+            let (dx, dy) = ((i % N).min(N - i % N) as f64, (i / N).min(N - i / N) as f64);
+            (-(dx * dx + dy * dy) / (2.0 * SIGMA * SIGMA)).exp()
+        }).collect();
+
+        // a tiny bit of noise to break the ties, otherwise the first points end up on a regular grid
+        let mut energy: Vec<f64> = (0..N * N).map(|i| unit(hash(&[i as u64]) as u32) * 1e-9).collect();
+        let mut rank = vec![usize::MAX; N * N];
+        for r in 0..N * N {
+            let p = (0..N * N).filter(|i| rank[*i] == usize::MAX)
+                .min_by(|a, b| energy[*a].total_cmp(&energy[*b])).unwrap();
+            rank[p] = r;
+            let (px, py) = (p % N, p / N);
+            for (i, e) in energy.iter_mut().enumerate() {
+                let (dx, dy) = ((i % N + N - px) % N, (i / N + N - py) % N);
+                *e += kernel[dy * N + dx];
+            }
+        }
+
+        rank.iter().map(|r| (*r as f64 + 0.5) / (N * N) as f64).collect()
+    })
+}
+
+// Shirley and Chiu's concentric mapping, which keeps strata next to each other on the disc
+pub fn disc(u: Vector2<f64>) -> Vector2<f64> {
+    let (a, b) = (u[0] * 2.0 - 1.0, u[1] * 2.0 - 1.0);
+    if a == 0.0 && b == 0.0 { return Vector2::default() }
+
+    let (r, phi) = if a.abs() > b.abs() {
+        (a, PI / 4.0 * (b / a))
+    } else {
+        (b, PI / 2.0 - PI / 4.0 * (a / b))
+    };
+    Vector2::new(phi.cos(), phi.sin()) * r
+}
+
+// a uniform direction
+pub fn sphere(u: Vector2<f64>) -> Vector3<f64> {
+    let z = 1.0 - 2.0 * u[0];
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = TAU * u[1];
+    Vector3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+// a direction around `n`, more likely the closer it is to `n` (cosine weighted)
+pub fn hemisphere(n: Vector3<f64>, u: Vector2<f64>) -> Vector3<f64> {
+    let d = disc(u);
+    let z = (1.0 - d.norm_squared()).max(0.0).sqrt();
+
+    // any two directions perpendicular to `n` and each other (Duff et al.)
+    let s = 1f64.copysign(n[2]);
+    let a = -1.0 / (s + n[2]);
+    let b = n[0] * n[1] * a;
+    let t = Vector3::new(1.0 + s * n[0] * n[0] * a, s * b, -s * n[0]);
+    let bt = Vector3::new(b, s + n[1] * n[1] * a, -n[1]);
+
+    t * d[0] + bt * d[1] + n * z
+}
+
+// a uniform point on a triangle as barycentric weights of its second and third corner
+pub fn triangle(u: Vector2<f64>) -> Vector2<f64> {
+    let s = u[0].sqrt();
+    Vector2::new(s * (1.0 - u[1]), s * u[1])
+}
@@ -0,0 +1,306 @@
+use nalgebra::base::*;
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::renderer::*;
+
+pub const DEFAULT_SCENE: &str = "scene.txt";
+
+#[derive(Clone, Debug)]
+pub enum Shape {
+    Sphere { c: Vector3<f64>, r: f64 },
+    Triangle([Vector3<f64>; 3]),
+    Floor { height: f64, size: f64 },
+    Obj { path: String, model: usize, scale: f64, offset: Vector3<f64> },
+}
+
+// one line of a scene file, which always turns into exactly one `Object`
+#[derive(Clone)]
+pub struct Entry {
+    pub shape: Shape,
+    // `None` keeps the material from the obj's mtl file
+    pub material: Option<Material>,
+    pub transform: Transform,
+    pub visible: bool,
+    pub casts_shadow: bool,
+    pub emits_light: bool,
+}
+
+impl Entry {
+    pub fn new(shape: Shape, material: Option<Material>) -> Self {
+        Self { shape, material, transform: Transform::default(), visible: true, casts_shadow: true, emits_light: true }
+    }
+}
+
+pub fn load(path: &Path) -> Result<Vec<Entry>, Box<dyn std::error::Error>> {
+    parse(&std::fs::read_to_string(path)?).map_err(|e| format!("{}:{e}", path.display()).into())
+}
+
+pub fn parse(s: &str) -> Result<Vec<Entry>, Box<dyn std::error::Error>> {
+    let mut entries = Vec::new();
+
+    for (ln, l) in s.lines().enumerate() {
+        let l = l.split('#').next().unwrap().trim();
+        let w: Vec<&str> = l.split_whitespace().collect();
+        let err = |m: &str| format!("{}: {m}", ln + 1);
+        let nums = |w: &[&str], n: usize| -> Result<Vec<f64>, String> {
+            if w.len() < n { return Err(err(&format!("expected {n} numbers"))) }
+            w[..n].iter().map(|v| v.parse().map_err(|_| err(&format!("`{v}` is not a number")))).collect()
+        };
+        let v3 = |n: &[f64]| Vector3::new(n[0], n[1], n[2]);
+
+        let (shape, mut rest) = match w.first() {
+            None => continue,
+            Some(&"sphere") => {
+                let n = nums(&w[1..], 4)?;
+                (Shape::Sphere { c: v3(&n), r: n[3] }, &w[5..])
+            },
+            Some(&"triangle") => {
+                let n = nums(&w[1..], 9)?;
+                (Shape::Triangle([v3(&n), v3(&n[3..]), v3(&n[6..])]), &w[10..])
+            },
+            Some(&"floor") => {
+                let n = nums(&w[1..], 2)?;
+                (Shape::Floor { height: n[0], size: n[1] }, &w[3..])
+            },
+            Some(&"obj") => {
+                if w.len() < 7 { return Err(err("expected `obj <path> <model> <scale> <x> <y> <z>`").into()) }
+                let model = w[2].parse().map_err(|_| err("model must be an index"))?;
+                let n = nums(&w[3..], 4)?;
+                (Shape::Obj { path: w[1].to_string(), model, scale: n[0], offset: v3(&n[1..]) }, &w[7..])
+            },
+            Some(s) => return Err(err(&format!("unknown shape `{s}`")).into()),
+        };
+
+        let mut e = Entry::new(shape, None);
+        while let Some((k, r)) = rest.split_first() {
+            let width = match *k {
+                "hidden" | "no_shadow" | "no_light" => 0,
+                "shininess" | "rough" => 1,
+                "color" | "emit" | "pos" | "rot" | "scale" => 3,
+                _ => return Err(err(&format!("unknown property `{k}`")).into()),
+            };
+            let n = nums(r, width)?;
+
+            match *k {
+                "color" => e.material.get_or_insert_with(Material::default).color = v3(&n),
+                "emit" => e.material.get_or_insert_with(Material::default).emit_color = v3(&n),
+                "shininess" => e.material.get_or_insert_with(Material::default).shininess = n[0],
+                "rough" => e.material.get_or_insert_with(Material::default).rough = n[0],
+                "pos" => e.transform.pos = v3(&n),
+                "rot" => e.transform.rot = v3(&n),
+                "scale" => e.transform.scale = v3(&n),
+                "hidden" => e.visible = false,
+                "no_shadow" => e.casts_shadow = false,
+                "no_light" => e.emits_light = false,
+                _ => unreachable!(),
+            }
+
+            rest = &r[width..];
+        }
+
+        if e.material.is_none() && !matches!(e.shape, Shape::Obj { .. }) {
+            e.material = Some(Material::default());
+        }
+
+        entries.push(e);
+    }
+
+    Ok(entries)
+}
+
+pub fn save(path: &Path, entries: &[Entry]) -> Result<(), Box<dyn std::error::Error>> {
+    std::fs::write(path, to_string(entries))?;
+    Ok(())
+}
+
+// FNV-1a over the scene as it is currently built and every obj it uses,
+// so it stays the same between builds and machines
+pub fn hash(entries: &[Entry], objects: &[Object]) -> Result<u64, Box<dyn std::error::Error>> {
+    let mut entries = entries.to_vec();
+    sync(&mut entries, objects);
+
+    let mut h: u64 = 0xcbf29ce484222325;
+    let mut feed = |b: &[u8]| for b in b {
+        h ^= *b as u64;
+        h = h.wrapping_mul(0x100000001b3);
+    };
+
+    feed(to_string(&entries).as_bytes());
+
+    let mut paths: Vec<&str> = entries.iter().filter_map(|e| match &e.shape {
+        Shape::Obj { path, .. } => Some(path.as_str()),
+        _ => None,
+    }).collect();
+    paths.sort();
+    paths.dedup();
+    for p in paths {
+        feed(&std::fs::read(p)?);
+    }
+
+    Ok(h)
+}
+
+pub fn to_string(entries: &[Entry]) -> String {
+    let v3 = |v: &Vector3<f64>| format!("{} {} {}", v[0], v[1], v[2]);
+
+    let mut s = String::new();
+    for e in entries.iter() {
+        s += &match &e.shape {
+            Shape::Sphere { c, r } => format!("sphere {} {r}", v3(c)),
+            Shape::Triangle(vp) => format!("triangle {} {} {}", v3(&vp[0]), v3(&vp[1]), v3(&vp[2])),
+            Shape::Floor { height, size } => format!("floor {height} {size}"),
+            Shape::Obj { path, model, scale, offset } => format!("obj {path} {model} {scale} {}", v3(offset)),
+        };
+
+        if let Some(m) = &e.material {
+            s += &format!(" color {} emit {} shininess {} rough {}", v3(&m.color), v3(&m.emit_color), m.shininess, m.rough);
+        }
+
+        let t = &e.transform;
+        let d = Transform::default();
+        if t.pos != d.pos { s += &format!(" pos {}", v3(&t.pos)) }
+        if t.rot != d.rot { s += &format!(" rot {}", v3(&t.rot)) }
+        if t.scale != d.scale { s += &format!(" scale {}", v3(&t.scale)) }
+
+        if !e.visible { s += " hidden" }
+        if !e.casts_shadow { s += " no_shadow" }
+        if !e.emits_light { s += " no_light" }
+
+        s += "\n";
+    }
+
+    s
+}
+
+// copies whatever was edited in the viewer back into the entries the objects were built from
+pub fn sync(entries: &mut [Entry], objects: &[Object]) {
+    for (e, o) in entries.iter_mut().zip(objects) {
+        e.material = Some(o.material.clone());
+        e.transform = *o.transform();
+        e.visible = o.visible;
+        e.casts_shadow = o.casts_shadow;
+        e.emits_light = o.emits_light;
+    }
+}
+
+pub fn build(entries: &[Entry]) -> Result<Vec<Object>, Box<dyn std::error::Error>> {
+    // obj files are loaded once no matter how many of their models are used
+    let mut objs: HashMap<String, Vec<(Mesh, Material)>> = HashMap::new();
+    let mut scene = Vec::with_capacity(entries.len());
+
+    for e in entries.iter() {
+        let (kind, material): (Box<dyn ObjectKind>, Material) = match &e.shape {
+            Shape::Sphere { c, r } => (Box::new(Sphere { c: *c, r: *r }), Material::default()),
+            Shape::Triangle(vp) => (Box::new(Triangle { vp: *vp, vn: None }), Material::default()),
+            Shape::Floor { height, size } => (Box::new(floor(*height, *size)), Material::default()),
+            Shape::Obj { path, model, scale, offset } => {
+                let key = format!("{path} {scale} {} {} {}", offset[0], offset[1], offset[2]);
+                if let std::collections::hash_map::Entry::Vacant(v) = objs.entry(key.clone()) {
+                    v.insert(load_obj(Path::new(path), *scale, *offset)?);
+                }
+                let (m, mat) = objs[&key].get(*model)
+                    .ok_or_else(|| format!("{path} has no model {model}"))?;
+                (Box::new(m.clone()), mat.clone())
+            },
+        };
+
+        let mut o = Object::new(kind, e.material.clone().unwrap_or(material));
+        o.set_transform(e.transform);
+        o.visible = e.visible;
+        o.casts_shadow = e.casts_shadow;
+        o.emits_light = e.emits_light;
+        scene.push(o);
+    }
+
+    Ok(scene)
+}
+
+pub fn floor(height: f64, size: f64) -> Mesh {
+    Mesh { ts: vec![
+        Triangle {
+            vp: [
+                Vector3::new(-size, height,  size),
+                Vector3::new( size, height,  size),
+                Vector3::new( size, height, -size),
+            ],
+            vn: None
+        },
+        Triangle {
+            vp: [
+                Vector3::new(-size, height,  size),
+                Vector3::new( size, height, -size),
+                Vector3::new(-size, height, -size),
+            ],
+            vn: None
+        },
+    ]}
+}
+
+pub fn load_obj(path: &Path, scale: f64, offset: Vector3<f64>) -> Result<Vec<(Mesh, Material)>, Box<dyn std::error::Error>> {
+    meshes(tobj::load_obj(path, &tobj::GPU_LOAD_OPTIONS)?, scale, offset)
+}
+
+// an obj that's already in memory, without any materials
+pub fn parse_obj(s: &str, scale: f64, offset: Vector3<f64>) -> Result<Vec<(Mesh, Material)>, Box<dyn std::error::Error>> {
+    let obj = tobj::load_obj_buf(&mut s.as_bytes(), &tobj::GPU_LOAD_OPTIONS, |_| Err(tobj::LoadError::OpenFileFailed))?;
+    meshes(obj, scale, offset)
+}
+
+fn meshes(obj: (Vec<tobj::Model>, Result<Vec<tobj::Material>, tobj::LoadError>), scale: f64, offset: Vector3<f64>) -> Result<Vec<(Mesh, Material)>, Box<dyn std::error::Error>> {
+    let (models, materials) = obj;
+
+    let mut buf = Vec::with_capacity(models.len());
+
+    for i in models.iter() {
+        let mesh = &i.mesh;
+
+        let mut ts = Vec::with_capacity(mesh.positions.len() / 3);
+
+        for j in mesh.indices.chunks(3) {
+            fn load(a: &[f64], j: &[u32], i: usize) -> Vector3<f64> {
+                Vector3::new(
+                    a[j[i] as usize * 3],
+                    a[j[i] as usize * 3 + 1],
+                    a[j[i] as usize * 3 + 2],
+                )
+            }
+
+            let p0 = load(&mesh.positions, j, 0);
+            let p1 = load(&mesh.positions, j, 1);
+            let p2 = load(&mesh.positions, j, 2);
+
+            let n0 = load(&mesh.normals, j, 0);
+            let n1 = load(&mesh.normals, j, 1);
+            let n2 = load(&mesh.normals, j, 2);
+
+            ts.push(Triangle {
+                vp: [p0*scale+offset, p1*scale+offset, p2*scale+offset],
+                vn: Some([n0, n1, n2])
+            })
+        }
+
+        let mat = match mesh.material_id {
+            Some(i) => {
+                fn to(a: [f64;3]) -> Vector3<f64> {
+                    Vector3::new(a[0], a[1], a[2])
+                }
+
+                let om = &materials.as_ref().unwrap()[i];
+                let mut nm = Material::default();
+
+                if let Some(d) = om.diffuse { nm.color = to(d); }
+                if let Some(a) = om.ambient { nm.emit_color = to(a); }
+                if let Some(s) = om.shininess { nm.shininess = s; }
+                if let Some(d) = om.dissolve { nm.rough = d; }
+
+                nm
+            },
+            None => Material::default()
+        };
+
+        buf.push((Mesh { ts }, mat))
+    }
+
+    Ok(buf)
+}
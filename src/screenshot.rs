@@ -0,0 +1,143 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::Ordering;
+use std::thread::JoinHandle;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::renderer::{Pixel, Progress, RendererState};
+
+pub const SCREENSHOT_PATH: &str = "screenshot.cfg";
+
+#[derive(Clone, Debug)]
+pub struct Settings {
+    pub width: usize,
+    pub height: usize,
+    pub samples: usize,
+    pub bounces: usize,
+    // `{n}` becomes the first number that isn't taken yet and `{time}` the current time, the extension picks the format
+    pub path: String,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            width: crate::terminal::SCREENSHOT_SIZE,
+            height: crate::terminal::SCREENSHOT_SIZE,
+            samples: crate::terminal::SCREENSHOT_SAMPLES,
+            bounces: crate::renderer::LIGHT_BOUNCES,
+            path: "screenshot_{n}.png".to_string(),
+        }
+    }
+}
+
+impl Settings {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut s = Self::default();
+        if !path.exists() { return Ok(s) }
+
+        for (ln, l) in std::fs::read_to_string(path)?.lines().enumerate() {
+            let l = l.split('#').next().unwrap().trim();
+            let err = |m: &str| format!("{}:{}: {m}", path.display(), ln + 1);
+
+            let Some((k, v)) = l.split_once(char::is_whitespace) else {
+                if l.is_empty() { continue }
+                return Err(err("expected `<name> <value>`").into());
+            };
+            let v = v.trim();
+            let num = || match v.parse::<usize>() {
+                Ok(n) if n > 0 => Ok(n),
+                _ => Err(err(&format!("`{v}` is not a positive whole number"))),
+            };
+
+            match k {
+                "width" => s.width = num()?,
+                "height" => s.height = num()?,
+                "samples" => s.samples = num()?,
+                "bounces" => s.bounces = num()?,
+                "path" => s.path = v.to_string(),
+                _ => return Err(err(&format!("unknown setting `{k}`")).into()),
+            }
+        }
+
+        crate::output::format(Path::new(&s.path))?;
+        Ok(s)
+    }
+}
+
+pub fn output_path(template: &str) -> PathBuf {
+    let t = template.replace("{time}", &timestamp());
+    if !t.contains("{n}") { return t.into() }
+    (1..).map(|n| PathBuf::from(t.replace("{n}", &n.to_string()))).find(|p| !p.exists()).unwrap()
+}
+
+// UTC, as yyyymmdd-hhmmss
+fn timestamp() -> String {
+    let t = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()) as i64;
+    let (days, secs) = (t.div_euclid(86400), t.rem_euclid(86400));
+
+    // Howard Hinnant's civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + (m <= 2) as i64;
+
+    format!("{y:04}{m:02}{d:02}-{:02}{:02}{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+// a screenshot rendering on a thread of its own, from a copy of the scene so the viewer can carry on meanwhile
+pub struct Export {
+    pub path: PathBuf,
+    state: Arc<RendererState>,
+    progress: Arc<Mutex<Option<Progress>>>,
+    thread: JoinHandle<Result<bool, String>>,
+}
+
+impl Export {
+    pub fn start(rs: RendererState, s: &Settings, opts: crate::output::Options) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = output_path(&s.path);
+        let ext = crate::output::format(&path)?;
+
+        let state = Arc::new(rs);
+        let progress = Arc::new(Mutex::new(None));
+        let thread = {
+            let (state, progress, s) = (state.clone(), progress.clone(), s.clone());
+            // written next to it first, so a half written file never has the real name
+            let (tmp, path) = (path.with_extension(format!("{ext}.part")), path.clone());
+            std::thread::spawn(move || {
+                let mut img = vec![vec![Pixel::default(); s.width]; s.height];
+                let finished = crate::renderer::render_progress(&state, s.width, s.height, &mut img, s.samples, |p, _| {
+                    *progress.lock().unwrap() = Some(*p);
+                });
+                if !finished { return Ok(false) }
+
+                crate::output::save(&tmp, &crate::output::layers(&img, opts), opts).map_err(|e| e.to_string())?;
+                std::fs::rename(&tmp, &path).map_err(|e| e.to_string())?;
+                Ok(true)
+            })
+        };
+
+        Ok(Self { path, state, progress, thread })
+    }
+
+    pub fn progress(&self) -> Option<Progress> {
+        *self.progress.lock().unwrap()
+    }
+
+    pub fn cancel(&self) {
+        self.state.cancel.store(true, Ordering::Relaxed);
+    }
+
+    pub fn finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    // whether the image was saved, false if it was cancelled
+    pub fn join(self) -> Result<bool, String> {
+        self.thread.join().unwrap_or_else(|_| Err("the render thread panicked".to_string()))
+    }
+}
@@ -0,0 +1,178 @@
+use std::cell::Cell;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Counter {
+    // one per path, from the camera
+    Primary,
+    Bounce,
+    // towards a light, to see whether anything is in the way
+    Shadow,
+    // a ray against an object, a mesh counts one for every triangle since there's no BVH to skip any of them
+    Intersections,
+    // paths that Russian roulette cut short
+    Roulette,
+}
+
+// in the order of the enum
+pub const COUNTERS: &[(Counter, &str)] = &[
+    (Counter::Primary,       "primary_rays"),
+    (Counter::Bounce,        "bounce_rays"),
+    (Counter::Shadow,        "shadow_rays"),
+    (Counter::Intersections, "intersection_tests"),
+    (Counter::Roulette,      "roulette_terminations"),
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Phase {
+    // the tiles, from the start of a round to the last one finishing
+    Trace,
+    // adding the tiles to the image
+    Merge,
+    // tone mapping, denoising and post processing
+    Post,
+    // drawing the viewer
+    Display,
+    // images and checkpoints
+    Save,
+}
+
+pub const PHASES: &[(Phase, &str)] = &[
+    (Phase::Trace,   "trace"),
+    (Phase::Merge,   "merge"),
+    (Phase::Post,    "post"),
+    (Phase::Display, "display"),
+    (Phase::Save,    "save"),
+];
+
+const N: usize = COUNTERS.len();
+
+thread_local! {
+    // counted per thread so tracing doesn't fight over the atomics, and flushed into `Stats` once per tile
+    static LOCAL: [Cell<u64>; N] = const { [const { Cell::new(0) }; N] };
+}
+
+pub fn count(c: Counter, n: u64) {
+    LOCAL.with(|l| l[c as usize].set(l[c as usize].get() + n));
+}
+
+#[derive(Default, Debug)]
+pub struct Stats {
+    counters: [AtomicU64; N],
+    nanos: [AtomicU64; PHASES.len()],
+}
+
+impl Stats {
+    // adds what this thread counted since it last did
+    pub fn flush(&self) {
+        LOCAL.with(|l| for (c, l) in self.counters.iter().zip(l) {
+            c.fetch_add(l.take(), Ordering::Relaxed);
+        });
+    }
+
+    pub fn time(&self, p: Phase, d: Duration) {
+        self.nanos[p as usize].fetch_add(d.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub fn reset(&self) {
+        self.counters.iter().chain(&self.nanos).for_each(|c| c.store(0, Ordering::Relaxed));
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            counters: self.counters.each_ref().map(|c| c.load(Ordering::Relaxed)),
+            times: self.nanos.each_ref().map(|n| Duration::from_nanos(n.load(Ordering::Relaxed))),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Snapshot {
+    pub counters: [u64; N],
+    pub times: [Duration; PHASES.len()],
+}
+
+impl Snapshot {
+    pub fn get(&self, c: Counter) -> u64 {
+        self.counters[c as usize]
+    }
+
+    pub fn time(&self, p: Phase) -> Duration {
+        self.times[p as usize]
+    }
+
+    // what happened since `earlier`
+    pub fn since(&self, earlier: &Snapshot) -> Snapshot {
+        Snapshot {
+            counters: std::array::from_fn(|i| self.counters[i].saturating_sub(earlier.counters[i])),
+            times: std::array::from_fn(|i| self.times[i].saturating_sub(earlier.times[i])),
+        }
+    }
+
+    pub fn rays(&self) -> u64 {
+        self.get(Counter::Primary) + self.get(Counter::Bounce) + self.get(Counter::Shadow)
+    }
+
+    // over the time spent tracing, so it doesn't change with what the rest of the program does
+    pub fn mrays_per_sec(&self) -> f64 {
+        self.rays() as f64 / self.time(Phase::Trace).as_secs_f64().max(1e-9) / 1e6
+    }
+
+    // segments per path, not counting the shadow rays
+    pub fn path_length(&self) -> f64 {
+        (self.get(Counter::Primary) + self.get(Counter::Bounce)) as f64 / self.get(Counter::Primary).max(1) as f64
+    }
+
+    pub fn tests_per_ray(&self) -> f64 {
+        self.get(Counter::Intersections) as f64 / self.rays().max(1) as f64
+    }
+
+    // one flat object, `extra` goes first
+    pub fn json(&self, extra: &[(&str, f64)], elapsed: Duration) -> String {
+        let mut f: Vec<(String, String)> = vec![("version".to_string(), format!("\"{}\"", env!("CARGO_PKG_VERSION")))];
+        f.extend(extra.iter().map(|(k, v)| (k.to_string(), v.to_string())));
+        f.push(("seconds".to_string(), elapsed.as_secs_f64().to_string()));
+        f.extend(COUNTERS.iter().map(|(c, n)| (n.to_string(), self.get(*c).to_string())));
+        f.push(("rays".to_string(), self.rays().to_string()));
+        f.push(("mrays_per_sec".to_string(), self.mrays_per_sec().to_string()));
+        f.push(("average_path_length".to_string(), self.path_length().to_string()));
+        f.push(("tests_per_ray".to_string(), self.tests_per_ray().to_string()));
+        f.extend(PHASES.iter().map(|(p, n)| (format!("{n}_seconds"), self.time(*p).as_secs_f64().to_string())));
+        if let Some(m) = peak_memory() {
+            f.push(("peak_memory_mib".to_string(), (m as f64 / (1024.0 * 1024.0)).to_string()));
+        }
+
+        let f: Vec<String> = f.iter().map(|(k, v)| format!("  \"{k}\": {v}")).collect();
+        format!("{{\n{}\n}}\n", f.join(",\n"))
+    }
+}
+
+// resident memory now, in bytes
+pub fn memory_use() -> Option<usize> {
+    status("VmRSS:")
+}
+
+// the most resident memory the process ever had, in bytes
+pub fn peak_memory() -> Option<usize> {
+    status("VmHWM:")
+}
+
+// one of the sizes in /proc/self/status, which are in kB whatever the page size is
+fn status(field: &str) -> Option<usize> {
+    let s = std::fs::read_to_string("/proc/self/status").ok()?;
+    let kb: usize = s.lines().find_map(|l| l.strip_prefix(field))?.trim().trim_end_matches("kB").trim().parse().ok()?;
+    Some(kb * 1024)
+}
+
+// a count with a metric suffix and three digits, short enough for the panel
+pub fn si(n: u64) -> String {
+    let (v, s) = match n {
+        0..=999 => return n.to_string(),
+        1_000..=999_999 => (n as f64 / 1e3, "k"),
+        1_000_000..=999_999_999 => (n as f64 / 1e6, "M"),
+        _ => (n as f64 / 1e9, "G"),
+    };
+    let d = if v < 10.0 { 2 } else if v < 100.0 { 1 } else { 0 };
+    format!("{v:.d$}{s}")
+}
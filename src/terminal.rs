@@ -2,35 +2,261 @@ use nalgebra::base::*;
 use crossterm::{*, style::{Color, Stylize}, event::*};
 use std::io::{stdout, Write};
 use std::time::*;
-use std::f64::consts::TAU;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::keymap::{Action, Keymap};
+use crate::stats::{si, Counter, Phase, Snapshot};
 
 pub const SCREENSHOT_SIZE: usize = 256;
 pub const SCREENSHOT_SAMPLES: usize = 4;
+pub const CAMERA_PATH: &str = "camera_path.anim";
+pub const CAMERA_PATH_FPS: f64 = 24.0;
+// without key release events a held key counts as released once its repeats stop for this long
+pub const HOLD_WINDOW: Duration = Duration::from_millis(100);
+pub const MAX_HOLD_STEP: f64 = 0.25;
+pub const PANEL_WIDTH: u16 = 34;
+pub const OUTLINER_ROWS: usize = 12;
+// per step, relative to `move_step` and as a scale factor
+pub const TRANSLATE_STEP: f64 = 0.1;
+pub const SCALE_STEP: f64 = 0.1;
+// in stops and kelvin
+pub const EXPOSURE_STEP: f64 = 0.25;
+pub const WHITE_BALANCE_STEP: f64 = 250.0;
+pub const TARGET_FPS: f64 = 30.0;
+pub const NOTICE_TIME: Duration = Duration::from_secs(5);
 
-pub static mut LOGS: Vec<(SystemTime, String)> = Vec::new();
+// material properties that can be edited on a picked object, with how much one key press changes them
+pub const MATERIAL_FIELDS: &[(&str, f64)] = &[
+    ("color r", 0.05),
+    ("color g", 0.05),
+    ("color b", 0.05),
+    ("emit r", 0.5),
+    ("emit g", 0.5),
+    ("emit b", 0.5),
+    ("shininess", 0.05),
+    ("rough", 0.05),
+];
 
-pub fn push_log(s: &str) {
-    unsafe {
-        let t = SystemTime::now();
-        LOGS.push((t, s.to_string()));
+fn material_field(m: &mut crate::renderer::Material, f: usize) -> &mut f64 {
+    match f {
+        0..=2 => &mut m.color[f],
+        3..=5 => &mut m.emit_color[f - 3],
+        6 => &mut m.shininess,
+        _ => &mut m.rough,
     }
 }
 
+static KEY_RELEASE: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum TransformMode {
+    #[default]
+    Off,
+    Translate,
+    Rotate,
+    Scale,
+}
+
+// what the value and movement keys act on
+#[derive(Clone, Copy, Default)]
+pub struct Edit {
+    pub selected: Option<usize>,
+    pub field: usize,
+    pub mode: TransformMode,
+}
+
+#[derive(Default)]
+pub struct ViewerState {
+    pub recording: Option<(SystemTime, Vec<(f64, crate::renderer::Pose)>)>,
+    pub playback: Option<(SystemTime, crate::anim::Animation)>,
+    pub bookmarks: crate::bookmarks::Bookmarks,
+    pub marking: bool,
+    pub keymap: Keymap,
+    pub held: HashMap<Action, (Instant, bool, bool)>,
+    pub last_update: Option<Instant>,
+    pub hud: bool,
+    pub help: bool,
+
+    pub size: usize,
+    pub scene: Vec<crate::scene::Entry>,
+    pub scene_path: std::path::PathBuf,
+    pub edit: Edit,
+    pub outliner: bool,
+
+    // which of the render's buffers is on screen
+    pub aov: crate::renderer::Aov,
+    pub denoise: bool,
+    pub denoiser: crate::denoise::Settings,
+    pub post: crate::post::Post,
+
+    pub checkpoint: Option<std::path::PathBuf>,
+    pub save_checkpoint: bool,
+    pub quit: bool,
+
+    // how long a frame should take at the target frame rate
+    pub frame: Duration,
+
+    pub screenshot: crate::screenshot::Settings,
+    pub export: Option<crate::screenshot::Export>,
+    // shown in the status line for a while
+    pub notice: Option<(Instant, String)>,
+}
+
+impl ViewerState {
+    // `frame` is what the last frame did, the counts are for the whole image
+    pub fn panel(&self, state: &crate::renderer::RendererState, spp: usize, frame: &Snapshot) -> Vec<String> {
+        let mut p = Vec::new();
+        if self.hud {
+            let mode = self.mode().trim();
+            p.push(format!("pos   {:.2} {:.2} {:.2}", state.cam_pos[0], state.cam_pos[1], state.cam_pos[2]));
+            p.push(format!("rot   {:.1}\u{b0} {:.1}\u{b0}", state.rot[0].to_degrees(), state.rot[1].to_degrees()));
+            p.push(format!("focus {:.3} aperture {:.2}", state.focus, state.aperture));
+            p.push(format!("spp   {spp}"));
+            if let Some(t) = state.noise_threshold {
+                let active = state.active_pixels as f64 / (self.size * self.size).max(1) as f64;
+                p.push(format!("noise {t} active {:.0}%", active * 100.0));
+            }
+            let st = state.stats.snapshot();
+            p.push(format!("rays  {:.2} Mray/s {:.1} tests/ray", frame.mrays_per_sec(), st.tests_per_ray()));
+            p.push(format!("pri   {} bnc {} shd {}", si(st.get(Counter::Primary)), si(st.get(Counter::Bounce)), si(st.get(Counter::Shadow))));
+            let rr = st.get(Counter::Roulette) as f64 / st.get(Counter::Primary).max(1) as f64;
+            p.push(format!("path  {:.2} long rr {:.0}%", st.path_length(), rr * 100.0));
+            let ms = |ph| frame.time(ph).as_secs_f64() * 1000.0;
+            p.push(format!("time  trace {:.0} post {:.0} draw {:.0} ms", ms(Phase::Trace) + ms(Phase::Merge), ms(Phase::Post), ms(Phase::Display)));
+            p.push(format!("bounces {} sampler {}", state.bounces, state.sampler.name()));
+            p.push(format!("filter {}", state.filter.name()));
+            p.push(format!("mode  {}", if mode.is_empty() { "view" } else { mode }));
+            p.push(format!("buf   {}", self.aov.name()));
+            let tm = &state.tonemap;
+            p.push(format!("tone  {} {:+.2} EV {:.0}K", tm.operator.name(), tm.exposure, tm.white_balance));
+            let post: Vec<&str> = [(self.post.bloom, "bloom"), (self.post.vignette, "vignette"), (self.post.chromatic, "ca"), (self.post.grain, "grain")]
+                .iter().filter(|e| e.0).map(|e| e.1).collect();
+            p.push(format!("post  {}", if post.is_empty() { "off".to_string() } else { post.join(" ") }));
+            p.push(match self.denoise {
+                true => format!("denoise {} levels strength {:.2}", self.denoiser.levels, self.denoiser.strength),
+                false => "denoise off".to_string(),
+            });
+            p.push(match crate::stats::memory_use() {
+                Some(m) => format!("mem   {:.1} MiB", m as f64 / (1024.0 * 1024.0)),
+                None => "mem   n/a".to_string(),
+            });
+        }
+
+        if self.outliner {
+            if !p.is_empty() { p.push(String::new()) }
+
+            let cur = self.edit.selected.unwrap_or(0);
+            let first = cur.saturating_sub(OUTLINER_ROWS / 2).min(state.scene.len().saturating_sub(OUTLINER_ROWS));
+            for (i, o) in state.scene.iter().enumerate().skip(first).take(OUTLINER_ROWS) {
+                let flag = |b, c| if b { c } else { '-' };
+                let c = o.material.color.map(|v| (v.clamp(0.0, 1.0) * 255.0) as u8);
+                p.push(format!("{}{i:>4} {:<8}{:>6} {}{}{} #{:02X}{:02X}{:02X}",
+                    if Some(i) == self.edit.selected { ">" } else { " " },
+                    o.kind.name(), o.kind.triangles(),
+                    flag(o.visible, 'v'), flag(o.casts_shadow, 's'), flag(o.emits_light, 'l'),
+                    c[0], c[1], c[2],
+                ));
+            }
+            p.push(format!("{} objects", state.scene.len()));
+        }
+
+        if let Some(i) = self.edit.selected {
+            if !p.is_empty() { p.push(String::new()) }
+
+            let o = &state.scene[i];
+            let mut m = o.material.clone();
+            p.push(format!("object {i} {}", o.kind.name()));
+            if self.edit.mode != TransformMode::Off {
+                let t = o.transform();
+                p.push(format!("{:?}", self.edit.mode).to_lowercase());
+                p.push(format!("  pos   {:.2} {:.2} {:.2}", t.pos[0], t.pos[1], t.pos[2]));
+                p.push(format!("  rot   {:.1}\u{b0} {:.1}\u{b0} {:.1}\u{b0}", t.rot[0].to_degrees(), t.rot[1].to_degrees(), t.rot[2].to_degrees()));
+                p.push(format!("  scale {:.2} {:.2} {:.2}", t.scale[0], t.scale[1], t.scale[2]));
+            }
+            for (f, (n, _)) in MATERIAL_FIELDS.iter().enumerate() {
+                let cur = if f == self.edit.field { ">" } else { " " };
+                p.push(format!("{cur} {n:<10} {:.3}", material_field(&mut m, f)));
+            }
+        }
+
+        if self.help {
+            if !p.is_empty() { p.push(String::new()) }
+            for (a, n, _) in crate::keymap::ACTIONS {
+                p.push(format!("{n:<14} {}", self.keymap.keys_for(*a).join(" ")));
+            }
+            p.push(format!("{:<14} 0-9", "bookmark"));
+        }
+
+        p
+    }
+
+    // the screenshot being rendered, or the last thing that happened to one
+    pub fn notice(&self) -> String {
+        if let Some(e) = &self.export {
+            return match e.progress() {
+                Some(p) => format!(" [{} pass {}/{} {:.0}% eta {}]", e.path.display(), p.pass, p.passes, p.fraction() * 100.0,
+                    p.eta().map_or("?".to_string(), |t| format!("{:.0}s", t.as_secs_f64().ceil()))),
+                None => format!(" [{}]", e.path.display()),
+            };
+        }
+        match &self.notice {
+            Some((t, n)) if t.elapsed() < NOTICE_TIME => format!(" [{n}]"),
+            _ => String::new(),
+        }
+    }
+
+    pub fn mode(&self) -> &'static str {
+        if self.recording.is_some() {
+            " [rec]"
+        } else if self.playback.is_some() {
+            " [play]"
+        } else if self.marking {
+            " [mark 0-9]"
+        } else if self.edit.selected.is_some() && self.edit.mode != TransformMode::Off {
+            match self.edit.mode {
+                TransformMode::Translate => " [translate]",
+                TransformMode::Rotate => " [rotate]",
+                _ => " [scale]",
+            }
+        } else {
+            ""
+        }
+    }
+}
+
+pub static LOGS: std::sync::Mutex<Vec<(SystemTime, String)>> = std::sync::Mutex::new(Vec::new());
+
+pub fn push_log(s: &str) {
+    let t = SystemTime::now();
+    LOGS.lock().unwrap().push((t, s.to_string()));
+}
+
 pub fn init() -> core::result::Result<u16, Box<dyn std::error::Error>> {
     terminal::enable_raw_mode()?;
     execute!(stdout(),
         terminal::EnterAlternateScreen,
         terminal::Clear(terminal::ClearType::All),
-        cursor::Hide
+        cursor::Hide,
+        EnableMouseCapture
     )?;
 
+    if terminal::supports_keyboard_enhancement().unwrap_or(false) {
+        execute!(stdout(), PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES))?;
+        KEY_RELEASE.store(true, Ordering::Relaxed);
+    }
+
     let (c, r) = terminal::size()?;
 
     Ok(c.min(r << 1).min(50))
 }
 
 pub fn prep_exit() -> core::result::Result<(), Box<dyn std::error::Error>> {
+    if KEY_RELEASE.load(Ordering::Relaxed) {
+        execute!(stdout(), PopKeyboardEnhancementFlags)?;
+    }
     execute!(stdout(),
+        DisableMouseCapture,
         terminal::LeaveAlternateScreen,
         cursor::Show
     )?;
@@ -39,8 +265,21 @@ pub fn prep_exit() -> core::result::Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-pub fn push_image(image: Vec<Vec<(u8, u8, u8)>>, msg: &str) -> core::result::Result<(), Box<dyn std::error::Error>> {
+pub fn exit() -> core::result::Result<(), Box<dyn std::error::Error>> {
+    let mut s = String::new();
+
+    for i in LOGS.lock().unwrap().iter() {
+        s += &format!("{:.02}s ago: {}\n", i.0.elapsed()?.as_secs_f64(), i.1);
+    }
+
+    std::fs::write("logs.txt", s)?;
+
+    prep_exit()
+}
+
+pub fn push_image(image: Vec<Vec<(u8, u8, u8)>>, msg: &str, panel: &[String], crosshair: bool) -> core::result::Result<(), Box<dyn std::error::Error>> {
     let mut so = stdout();
+    let width = image.first().map_or(0, |r| r.len()) as u16;
     queue!(so, cursor::MoveTo(0, 0))?;
     for y in image.chunks(2) {
         for xi in 0..y[0].len() {
@@ -54,7 +293,7 @@ pub fn push_image(image: Vec<Vec<(u8, u8, u8)>>, msg: &str) -> core::result::Res
                 )
             )?;
         }
-        queue!(so, cursor::MoveToNextLine(1))?;
+        queue!(so, terminal::Clear(terminal::ClearType::UntilNewLine), cursor::MoveToNextLine(1))?;
     }
 
     queue!(so,
@@ -62,113 +301,388 @@ pub fn push_image(image: Vec<Vec<(u8, u8, u8)>>, msg: &str) -> core::result::Res
         style::PrintStyledContent(
             msg .with(Color::White)
                 .on  (Color::DarkGrey)
-        )
+        ),
+        terminal::Clear(terminal::ClearType::FromCursorDown)
     )?;
 
+    // marks what `pick` would select
+    if crosshair {
+        queue!(so,
+            cursor::MoveTo(width / 2, image.len() as u16 / 4),
+            style::PrintStyledContent("+".with(Color::White).on(Color::Black))
+        )?;
+    }
+
+    // the panel goes beside the image when there is room for it, otherwise over it
+    let x = if terminal::size()?.0 >= width + 1 + PANEL_WIDTH { width + 1 } else { 0 };
+    for (i, l) in panel.iter().enumerate() {
+        let l: String = l.chars().take(PANEL_WIDTH as usize).collect();
+        queue!(so,
+            cursor::MoveTo(x, i as u16),
+            style::PrintStyledContent(
+                format!("{l:<w$}", w = PANEL_WIDTH as usize)
+                    .with(Color::White)
+                    .on  (Color::DarkGrey)
+            )
+        )?;
+    }
+
     so.flush()?;
 
     Ok(())
 }
 
-pub fn handle_input(state: &mut crate::renderer::RendererState, el: Duration) -> core::result::Result<bool, Box<dyn std::error::Error>> {
-    let pr = poll(
-        Duration::from_millis(
-            30_u128.checked_sub(el.as_millis()).unwrap_or(0) as u64
-        )
-    )?;
-    if pr {
-        match read()? {
-            Event::Key(KeyEvent { code: KeyCode::Esc, .. }) => {
-                unsafe {
-                    let mut s = String::new();
+// called once per frame before rendering, returns true if the accumulated image has to be thrown away
+pub fn update(state: &mut crate::renderer::RendererState, viewer: &mut ViewerState) -> bool {
+    let now = Instant::now();
+    let dt = viewer.last_update.map_or(0.0, |t| (now - t).as_secs_f64()).min(MAX_HOLD_STEP);
+    viewer.last_update = Some(now);
 
-                    for i in &LOGS {
-                        s += &format!("{:.02}s ago: {}\n", i.0.elapsed()?.as_secs_f64(), i.1);
-                    }
+    let release = KEY_RELEASE.load(Ordering::Relaxed);
+    let window = HOLD_WINDOW + Duration::from_secs_f64(dt);
+    viewer.held.retain(|_, h| release || now - h.0 < window);
 
-                    std::fs::write("logs.txt", s)?;
-                }
+    if viewer.export.as_ref().is_some_and(|e| e.finished()) {
+        let e = viewer.export.take().unwrap();
+        let path = e.path.clone();
+        let n = match e.join() {
+            Ok(true) => format!("saved {}", path.display()),
+            Ok(false) => "screenshot cancelled".to_string(),
+            Err(err) => format!("couldn't save {}: {err}", path.display()),
+        };
+        push_log(&n);
+        viewer.notice = Some((now, n));
+    }
 
-                prep_exit()?;
-                std::process::exit(0);
-            },
-            Event::Key(KeyEvent { code: KeyCode::F(12), kind: KeyEventKind::Press, .. }) => {
-                use std::path::Path;
-                use std::fs::File;
-                use std::io::BufWriter;
-
-                let mut so = stdout();
-                show(&mut so, "Rendering...")?;
-                push_log("Start render");
-
-                let path = Path::new(r"image_out.png");
-                let file = File::create(path)?;
-                let ref mut w = BufWriter::new(file);
-
-                let mut encoder = png::Encoder::new(w, SCREENSHOT_SIZE as u32, SCREENSHOT_SIZE as u32);
-                encoder.set_color(png::ColorType::Rgb);
-                encoder.set_depth(png::BitDepth::Eight);
-
-                let mut writer = encoder.write_header().unwrap();
-                let mut buf = Vec::with_capacity(SCREENSHOT_SIZE * SCREENSHOT_SIZE * 3);
-
-                let mut img = vec![vec![Vector3::default(); SCREENSHOT_SIZE]; SCREENSHOT_SIZE];
-                for i in 1..SCREENSHOT_SAMPLES {
-                    show(&mut so, &format!("Rendering sample {i}"))?;
-                    crate::renderer::render(state, SCREENSHOT_SIZE, &mut img, i);
-                }
-                show(&mut so, &format!("Rendering last sample"))?;
-                let img = crate::renderer::render(state, SCREENSHOT_SIZE, &mut img, SCREENSHOT_SAMPLES);
-
-                for y in img.iter() {
-                    for x in y.iter() {
-                        buf.push(x.0);
-                        buf.push(x.1);
-                        buf.push(x.2);
-                    }
+    let mut changed = false;
+    for (a, (_, fast, repeating)) in viewer.held.iter() {
+        if *repeating {
+            let n = viewer.keymap.hold_rate * dt * if *fast { viewer.keymap.fast_multiplier } else { 1.0 };
+            apply_step(state, viewer.edit, &viewer.keymap, *a, n);
+            changed = true;
+        }
+    }
+
+    if let Some((s, poses)) = &mut viewer.recording {
+        let t = s.elapsed().unwrap_or_default().as_secs_f64();
+        if poses.last().is_none_or(|l| l.0 < t) {
+            poses.push((t, state.pose()));
+        }
+    }
+
+    if let Some((s, a)) = &viewer.playback {
+        let t = s.elapsed().unwrap_or_default().as_secs_f64();
+        a.apply(state, t);
+        if t > a.duration() {
+            viewer.playback = None;
+            push_log("Playback finished");
+        }
+        return true;
+    }
+
+    changed
+}
+
+pub fn handle_input(state: &mut crate::renderer::RendererState, viewer: &mut ViewerState, el: Duration) -> core::result::Result<bool, Box<dyn std::error::Error>> {
+    let mut changed = false;
+    let mut timeout = viewer.frame.saturating_sub(el);
+
+    // drain everything that piled up while rendering so held keys don't lag behind
+    while poll(timeout)? {
+        timeout = Duration::ZERO;
+        match read()? {
+            Event::Key(k) => changed |= handle_key(state, viewer, k)?,
+            Event::Mouse(MouseEvent { kind: MouseEventKind::Down(MouseButton::Left), column, row, .. }) => {
+                let (x, y) = (column as usize, row as usize * 2);
+                if x < viewer.size && y < viewer.size {
+                    pick(state, viewer, crate::renderer::camera_ray(state, viewer.size, x, y));
                 }
-                writer.write_image_data(&buf)?;
-                push_log("End render");
             },
-  
-            Event::Key(KeyEvent { code: KeyCode::Char('a'), kind: KeyEventKind::Press, .. }) => state.cam_pos += crate::renderer::rotate(Vector3::x() *  0.1225, state.rot),
-            Event::Key(KeyEvent { code: KeyCode::Char('d'), kind: KeyEventKind::Press, .. }) => state.cam_pos += crate::renderer::rotate(Vector3::x() * -0.1225, state.rot),
-            Event::Key(KeyEvent { code: KeyCode::Char('q'), kind: KeyEventKind::Press, .. }) => state.cam_pos += crate::renderer::rotate(Vector3::y() *  0.1225, state.rot), 
-            Event::Key(KeyEvent { code: KeyCode::Char('e'), kind: KeyEventKind::Press, .. }) => state.cam_pos += crate::renderer::rotate(Vector3::y() * -0.1225, state.rot), 
-            Event::Key(KeyEvent { code: KeyCode::Char('w'), kind: KeyEventKind::Press, .. }) => state.cam_pos += crate::renderer::rotate(Vector3::z() *  0.1225, state.rot),
-            Event::Key(KeyEvent { code: KeyCode::Char('s'), kind: KeyEventKind::Press, .. }) => state.cam_pos += crate::renderer::rotate(Vector3::z() * -0.1225, state.rot),
-
-            Event::Key(KeyEvent { code: KeyCode::Down , kind: KeyEventKind::Press, .. }) => state.rot[0] += 1.0 / 16.0 * TAU, 
-            Event::Key(KeyEvent { code: KeyCode::Up   , kind: KeyEventKind::Press, .. }) => state.rot[0] -= 1.0 / 16.0 * TAU, 
-            Event::Key(KeyEvent { code: KeyCode::Right, kind: KeyEventKind::Press, .. }) => state.rot[1] += 1.0 / 16.0 * TAU, 
-            Event::Key(KeyEvent { code: KeyCode::Left , kind: KeyEventKind::Press, .. }) => state.rot[1] -= 1.0 / 16.0 * TAU, 
-
-            Event::Key(KeyEvent { code: KeyCode::Home, kind: KeyEventKind::Press, .. }) => state.focus += 0.125,
-            Event::Key(KeyEvent { code: KeyCode::End , kind: KeyEventKind::Press, .. }) => state.focus -= 0.125,
-            Event::Key(KeyEvent { code: KeyCode::Backspace, kind: KeyEventKind::Press, .. }) => {
-                let r = crate::renderer::Ray::new(state.cam_pos, crate::renderer::rotate(Vector3::z(), state.rot));
-                let h = r.try_hit(&state.scene);
-                if h.is_some() {
-                    let (h, _) = h.unwrap();
-                    state.focus = h.t
-                }
+            _ => (),
+        }
+    }
+
+    Ok(changed)
+}
+
+fn handle_key(state: &mut crate::renderer::RendererState, viewer: &mut ViewerState, k: KeyEvent) -> core::result::Result<bool, Box<dyn std::error::Error>> {
+    if let (KeyCode::Char(c @ '0'..='9'), KeyEventKind::Press) = (k.code, k.kind) {
+        let i = c as usize - '0' as usize;
+        if std::mem::take(&mut viewer.marking) {
+            viewer.bookmarks.poses[i] = Some(state.pose());
+            viewer.bookmarks.save()?;
+            push_log(&format!("Saved bookmark {i}"));
+        } else if let Some(p) = viewer.bookmarks.poses[i] {
+            state.set_pose(p);
+        } else {
+            push_log(&format!("No bookmark {i}"));
+        }
+        return Ok(true);
+    }
+
+    let Some(a) = viewer.keymap.action(k.code) else { return Ok(false) };
+
+    if k.kind == KeyEventKind::Release {
+        viewer.held.remove(&a);
+        return Ok(false);
+    }
+
+    if a.continuous() {
+        // the first press moves one step, repeats after it switch to smooth motion in `update`
+        let fast = k.modifiers.contains(viewer.keymap.fast_modifier);
+        match viewer.held.get_mut(&a) {
+            Some(h) => *h = (Instant::now(), fast, true),
+            None => {
+                viewer.held.insert(a, (Instant::now(), fast, false));
+                let n = if fast { viewer.keymap.fast_multiplier } else { 1.0 };
+                apply_step(state, viewer.edit, &viewer.keymap, a, n);
             },
-            Event::Key(KeyEvent { code: KeyCode::PageUp  , kind: KeyEventKind::Press, .. }) => state.aperture += 0.25,
-            Event::Key(KeyEvent { code: KeyCode::PageDown, kind: KeyEventKind::Press, .. }) => state.aperture -= 0.25,
-            _ => ()
         }
+        return Ok(true);
     }
 
-    Ok(pr)
+    if k.kind == KeyEventKind::Repeat { return Ok(false) }
+
+    match a {
+        // a running screenshot is cancelled first
+        Action::Quit if viewer.export.is_some() => {
+            viewer.export.as_ref().unwrap().cancel();
+            return Ok(false);
+        },
+        Action::Quit => {
+            viewer.quit = true;
+            return Ok(false);
+        },
+        Action::CycleBuffer => {
+            use crate::renderer::AOVS;
+            let i = AOVS.iter().position(|a| a.0 == viewer.aov).unwrap_or(0);
+            viewer.aov = AOVS[(i + 1) % AOVS.len()].0;
+            return Ok(false);
+        },
+        Action::Denoise => {
+            viewer.denoise = !viewer.denoise;
+            return Ok(false);
+        },
+        Action::DenoiseWeaker | Action::DenoiseStronger => {
+            let s = &mut viewer.denoiser.strength;
+            *s = if a == Action::DenoiseStronger { *s * 1.25 } else { *s / 1.25 }.clamp(0.1, crate::denoise::MAX_STRENGTH);
+            return Ok(false);
+        },
+        // only changes how the samples are shown, so nothing has to be rendered again
+        Action::ExposureUp | Action::ExposureDown | Action::WhiteBalanceUp | Action::WhiteBalanceDown | Action::Tonemap => {
+            use crate::tonemap::OPERATORS;
+            let tm = &mut state.tonemap;
+            match a {
+                Action::ExposureUp => tm.exposure += EXPOSURE_STEP,
+                Action::ExposureDown => tm.exposure -= EXPOSURE_STEP,
+                Action::WhiteBalanceUp => tm.white_balance = (tm.white_balance + WHITE_BALANCE_STEP).min(40000.0),
+                Action::WhiteBalanceDown => tm.white_balance = (tm.white_balance - WHITE_BALANCE_STEP).max(1000.0),
+                _ => {
+                    let i = OPERATORS.iter().position(|o| o.0 == tm.operator).unwrap_or(0);
+                    tm.operator = OPERATORS[(i + 1) % OPERATORS.len()].0;
+                },
+            }
+            return Ok(false);
+        },
+        Action::Bloom | Action::Vignette | Action::ChromaticAberration | Action::Grain => {
+            let p = &mut viewer.post;
+            let e = match a {
+                Action::Bloom => &mut p.bloom,
+                Action::Vignette => &mut p.vignette,
+                Action::ChromaticAberration => &mut p.chromatic,
+                _ => &mut p.grain,
+            };
+            *e = !*e;
+            return Ok(false);
+        },
+        Action::Sampler => {
+            use crate::sampler::KINDS;
+            let i = KINDS.iter().position(|k| k.0 == state.sampler).unwrap_or(0);
+            state.sampler = KINDS[(i + 1) % KINDS.len()].0;
+        },
+        Action::Filter => {
+            use crate::filter::FILTERS;
+            let i = FILTERS.iter().position(|f| f.0 == state.filter).unwrap_or(0);
+            state.filter = FILTERS[(i + 1) % FILTERS.len()].0;
+        },
+        Action::SaveCheckpoint => {
+            if viewer.checkpoint.is_some() {
+                viewer.save_checkpoint = true;
+            } else {
+                push_log("No checkpoint file, start with --checkpoint <file>");
+            }
+            return Ok(false);
+        },
+        // F12 again cancels the running one
+        Action::Screenshot if viewer.export.is_some() => {
+            viewer.export.as_ref().unwrap().cancel();
+            return Ok(false);
+        },
+        Action::Screenshot => {
+            let mut entries = viewer.scene.clone();
+            crate::scene::sync(&mut entries, &state.scene);
+            let mut rs = state.with_scene(crate::scene::build(&entries)?);
+            rs.bounces = viewer.screenshot.bounces;
+
+            let opts = crate::output::Options {
+                tonemap: state.tonemap,
+                post: viewer.post,
+                denoise: viewer.denoise.then_some(viewer.denoiser),
+                ..Default::default()
+            };
+            let e = crate::screenshot::Export::start(rs, &viewer.screenshot, opts)?;
+            push_log(&format!("Rendering {}", e.path.display()));
+            viewer.export = Some(e);
+            return Ok(false);
+        },
+        Action::AutoFocus => {
+            let r = crate::renderer::Ray::new(state.cam_pos, crate::renderer::rotate(Vector3::z(), state.rot));
+            if let Some((h, _)) = r.try_hit(&state.scene) {
+                state.focus = h.t
+            }
+        },
+        Action::Mark => viewer.marking = !viewer.marking,
+        Action::Hud => {
+            viewer.hud = !viewer.hud;
+            return Ok(false);
+        },
+        Action::Pick => {
+            let r = crate::renderer::Ray::new(state.cam_pos, crate::renderer::rotate(Vector3::z(), state.rot));
+            pick(state, viewer, r);
+            return Ok(false);
+        },
+        Action::FieldPrev => viewer.edit.field = (viewer.edit.field + MATERIAL_FIELDS.len() - 1) % MATERIAL_FIELDS.len(),
+        Action::FieldNext => viewer.edit.field = (viewer.edit.field + 1) % MATERIAL_FIELDS.len(),
+        Action::Outliner => {
+            viewer.outliner = !viewer.outliner;
+            return Ok(false);
+        },
+        Action::SelectPrev | Action::SelectNext => {
+            let n = state.scene.len();
+            if n == 0 { return Ok(false) }
+            viewer.edit.selected = Some(match (a, viewer.edit.selected) {
+                (Action::SelectPrev, Some(i)) => (i + n - 1) % n,
+                (Action::SelectPrev, None) => n - 1,
+                (_, Some(i)) => (i + 1) % n,
+                (_, None) => 0,
+            });
+            return Ok(false);
+        },
+        Action::ToggleVisible | Action::ToggleShadow | Action::ToggleLight => {
+            let Some(i) = viewer.edit.selected else { return Ok(false) };
+            let o = &mut state.scene[i];
+            match a {
+                Action::ToggleVisible => o.visible = !o.visible,
+                Action::ToggleShadow => o.casts_shadow = !o.casts_shadow,
+                _ => o.emits_light = !o.emits_light,
+            }
+        },
+        Action::TransformMode => {
+            viewer.edit.mode = match viewer.edit.mode {
+                TransformMode::Off => TransformMode::Translate,
+                TransformMode::Translate => TransformMode::Rotate,
+                TransformMode::Rotate => TransformMode::Scale,
+                TransformMode::Scale => TransformMode::Off,
+            };
+            return Ok(false);
+        },
+        Action::SaveScene => {
+            crate::scene::sync(&mut viewer.scene, &state.scene);
+            crate::scene::save(&viewer.scene_path, &viewer.scene)?;
+            push_log(&format!("Saved scene to {}", viewer.scene_path.display()));
+            return Ok(false);
+        },
+        Action::Help => {
+            viewer.help = !viewer.help;
+            return Ok(false);
+        },
+        Action::Record => {
+            match viewer.recording.take() {
+                Some((_, poses)) => {
+                    crate::anim::Animation::from_poses(&poses, CAMERA_PATH_FPS).save(std::path::Path::new(CAMERA_PATH))?;
+                    push_log(&format!("Saved {} camera poses to {CAMERA_PATH}", poses.len()));
+                },
+                None => {
+                    viewer.recording = Some((SystemTime::now(), vec![(0.0, state.pose())]));
+                    push_log("Start recording");
+                },
+            }
+        },
+        Action::Play => {
+            match viewer.playback.take() {
+                Some(_) => push_log("Playback stopped"),
+                None => match crate::anim::Animation::load(std::path::Path::new(CAMERA_PATH)).and_then(|a| a.check(state).map(|_| a)) {
+                    Ok(a) => viewer.playback = Some((SystemTime::now(), a)),
+                    Err(e) => push_log(&format!("Can't play {CAMERA_PATH}: {e}")),
+                },
+            }
+        },
+        _ => (),
+    }
+
+    Ok(true)
 }
 
-fn show(so: &mut std::io::Stdout, s: &str) -> core::result::Result<(), Box<dyn std::error::Error>> {
-    execute!(so,
-        cursor::MoveTo(0, 0),
-        style::PrintStyledContent(s
-            .with(Color::White)
-            .on  (Color::DarkGrey)
-        )
-    )?;
-    Ok(())
+fn pick(state: &crate::renderer::RendererState, viewer: &mut ViewerState, r: crate::renderer::Ray) {
+    viewer.edit.selected = r.try_hit_index(&state.scene).map(|(_, i)| i);
+}
+
+fn apply_step(state: &mut crate::renderer::RendererState, edit: Edit, km: &Keymap, a: Action, n: f64) {
+    let Some(i) = edit.selected else { return move_camera(state, km, a, n) };
+
+    if let Action::ValueUp | Action::ValueDown = a {
+        let f = edit.field;
+        let s = MATERIAL_FIELDS[f].1 * n * if a == Action::ValueUp { 1.0 } else { -1.0 };
+        let v = material_field(&mut state.scene[i].material, f);
+        *v = if (3..=5).contains(&f) { (*v + s).max(0.0) } else { (*v + s).clamp(0.0, 1.0) };
+        return;
+    }
+
+    // with a transform mode on, the movement keys move the selected object along the world axes instead of the camera
+    let axis = match a {
+        Action::MoveLeft => Vector3::x(),
+        Action::MoveRight => -Vector3::x(),
+        Action::MoveUp => Vector3::y(),
+        Action::MoveDown => -Vector3::y(),
+        Action::MoveForward => Vector3::z(),
+        Action::MoveBack => -Vector3::z(),
+        _ => Vector3::default(),
+    };
+    if edit.mode == TransformMode::Off || axis == Vector3::default() {
+        return move_camera(state, km, a, n);
+    }
+
+    let o = &mut state.scene[i];
+    let mut t = *o.transform();
+    match edit.mode {
+        TransformMode::Translate => t.pos += axis * km.move_step * TRANSLATE_STEP * n,
+        TransformMode::Rotate => t.rot += axis * km.turn_step * n,
+        _ => t.scale.component_mul_assign(&axis.map(|v| (1.0 + SCALE_STEP).powf(v * n))),
+    }
+    o.set_transform(t);
+}
+
+fn move_camera(state: &mut crate::renderer::RendererState, km: &Keymap, a: Action, n: f64) {
+    use crate::renderer::rotate;
+
+    let s = km.step(a) * n;
+    match a {
+        Action::MoveLeft     => state.cam_pos += rotate(Vector3::x(), state.rot) *  s,
+        Action::MoveRight    => state.cam_pos += rotate(Vector3::x(), state.rot) * -s,
+        Action::MoveUp       => state.cam_pos += rotate(Vector3::y(), state.rot) *  s,
+        Action::MoveDown     => state.cam_pos += rotate(Vector3::y(), state.rot) * -s,
+        Action::MoveForward  => state.cam_pos += rotate(Vector3::z(), state.rot) *  s,
+        Action::MoveBack     => state.cam_pos += rotate(Vector3::z(), state.rot) * -s,
+
+        Action::TurnDown     => state.rot[0] += s,
+        Action::TurnUp       => state.rot[0] -= s,
+        Action::TurnRight    => state.rot[1] += s,
+        Action::TurnLeft     => state.rot[1] -= s,
+
+        Action::FocusFar     => state.focus += s,
+        Action::FocusNear    => state.focus -= s,
+        Action::ApertureUp   => state.aperture += s,
+        Action::ApertureDown => state.aperture -= s,
+        _ => (),
+    }
 }
@@ -0,0 +1,140 @@
+use nalgebra::base::*;
+
+pub const NEUTRAL_KELVIN: f64 = 6500.0;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Operator {
+    // just clips, like the renderer always did
+    #[default]
+    Clamp,
+    Reinhard,
+    Aces,
+    Agx,
+}
+
+pub const OPERATORS: &[(Operator, &str)] = &[
+    (Operator::Clamp,    "clamp"),
+    (Operator::Reinhard, "reinhard"),
+    (Operator::Aces,     "aces"),
+    (Operator::Agx,      "agx"),
+];
+
+impl Operator {
+    pub fn name(&self) -> &'static str {
+        OPERATORS.iter().find(|o| o.0 == *self).unwrap().1
+    }
+
+    pub fn parse(s: &str) -> Result<Self, String> {
+        OPERATORS.iter().find(|o| o.1 == s).map(|o| o.0).ok_or_else(|| {
+            format!("unknown tone mapping `{s}`, expected one of {}", OPERATORS.iter().map(|o| o.1).collect::<Vec<_>>().join(", "))
+        })
+    }
+}
+
+// turns linear radiance into display colors
+#[derive(Clone, Copy, Debug)]
+pub struct Tonemap {
+    // in stops, +1 doubles the brightness
+    pub exposure: f64,
+    pub operator: Operator,
+    // color temperature of the light that should come out white
+    pub white_balance: f64,
+}
+
+impl Default for Tonemap {
+    fn default() -> Self {
+        Self { exposure: 0.0, operator: Operator::Clamp, white_balance: NEUTRAL_KELVIN }
+    }
+}
+
+impl Tonemap {
+    // linear display color in 0..1, before the transfer function
+    pub fn linear(&self, v: Vector3<f64>) -> Vector3<f64> {
+        let wb = kelvin(NEUTRAL_KELVIN).component_div(&kelvin(self.white_balance));
+        let v = v.component_mul(&wb).map(|c| c.max(0.0)) * 2f64.powf(self.exposure);
+
+        match self.operator {
+            Operator::Clamp => v,
+            Operator::Reinhard => v / (1.0 + luminance(&v)),
+            Operator::Aces => aces(v),
+            Operator::Agx => agx(v),
+        }.map(|c| c.clamp(0.0, 1.0))
+    }
+
+    pub fn apply(&self, v: Vector3<f64>) -> Vector3<f64> {
+        self.linear(v).map(srgb)
+    }
+
+    pub fn rgb8(&self, v: Vector3<f64>) -> (u8, u8, u8) {
+        let c = self.apply(v).map(|c| (c * 255.0 + 0.5) as u8);
+        (c[0], c[1], c[2])
+    }
+}
+
+pub fn luminance(v: &Vector3<f64>) -> f64 {
+    v.dot(&Vector3::new(0.2126, 0.7152, 0.0722))
+}
+
+// the sRGB OETF
+pub fn srgb(c: f64) -> f64 {
+    if c <= 0.0031308 { c * 12.92 } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 }
+}
+
+// and back, for images that were already encoded
+pub fn srgb_to_linear(c: f64) -> f64 {
+    if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+}
+
+// Stephen Hill's fit of the ACES reference rendering and output transforms
+fn aces(v: Vector3<f64>) -> Vector3<f64> {
+    let input = Matrix3::new(
+        0.59719, 0.35458, 0.04823,
+        0.07600, 0.90834, 0.01566,
+        0.02840, 0.13383, 0.83777,
+    );
+    let output = Matrix3::new(
+         1.60475, -0.53108, -0.07367,
+        -0.10208,  1.10813, -0.00605,
+        -0.00327, -0.07276,  1.07602,
+    );
+
+    let v = (input * v).map(|v| (v * (v + 0.0245786) - 0.000090537) / (v * (0.983729 * v + 0.4329510) + 0.238081));
+    output * v
+}
+
+// the polynomial approximation of AgX's default look from Benjamin Wrensch
+fn agx(v: Vector3<f64>) -> Vector3<f64> {
+    const MIN_EV: f64 = -12.47393;
+    const MAX_EV: f64 = 4.026069;
+
+    let inset = Matrix3::new(
+        0.842479062253094, 0.0784335999999992, 0.0792237451477643,
+        0.0423282422610123, 0.878468636469772, 0.0791661274605434,
+        0.0423756549057051, 0.0784336, 0.879142973793104,
+    );
+    let outset = Matrix3::new(
+        1.19687900512017, -0.0980208811401368, -0.0990297440797205,
+        -0.0528968517574562, 1.15190312990417, -0.0989611768448433,
+        -0.0529716355144438, -0.0980434501171241, 1.15107367264116,
+    );
+
+    let v = (inset * v).map(|c| {
+        let x = (c.max(1e-10).log2().clamp(MIN_EV, MAX_EV) - MIN_EV) / (MAX_EV - MIN_EV);
+        let x2 = x * x;
+        let x4 = x2 * x2;
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232
+    });
+    // the curve already includes a 2.2 display gamma, which is taken back out so everything ends with the same OETF
+    (outset * v).map(|c| c.max(0.0).powf(2.2))
+}
+
+// color of a black body, Tanner Helland's fit
+fn kelvin(k: f64) -> Vector3<f64> {
+    let t = k.clamp(1000.0, 40000.0) / 100.0;
+    let r = if t <= 66.0 { 255.0 } else { 329.698727446 * (t - 60.0).powf(-0.1332047592) };
+    let g = if t <= 66.0 { 99.4708025861 * t.ln() - 161.1195681661 } else { 288.1221695283 * (t - 60.0).powf(-0.0755148492) };
+    let b = if t >= 66.0 { 255.0 } else if t <= 19.0 { 0.0 } else { 138.5177312231 * (t - 10.0).ln() - 305.0447927307 };
+
+    // the fit is in display values, and blue running out below 1900K would divide by zero
+    Vector3::new(r, g, b).map(|c| (c.clamp(1.0, 255.0) / 255.0).powf(2.2))
+}